@@ -17,6 +17,9 @@ export enum MessageType {
 export class CommHandler extends PeerHandler<CommClientData> {
 	setting: ClientSetting;
 	chat: undefined | JQuery<HTMLElement> = undefined;
+	kick_callback?: (client_id: ClientID) => void = undefined;
+	bot_callback?: () => void = undefined;
+	mate_callback?: (client_id: ClientID) => void = undefined;
 
 	constructor(setting: ClientSetting) {
 		super();
@@ -56,7 +59,9 @@ export class CommHandler extends PeerHandler<CommClientData> {
                         <input id="chatInput" placeholder="Input"/>
                         <div id="chatHistory"></div>
                        </div>
-                       <div id="playerSettings"></div>
+                       <div id="playerSettings">
+                        <div id="addBot" class="ActionButton">Bot hinzufügen</div>
+                       </div>
                       </div>`);
 
 		let input = chat.find("#chatInput");
@@ -72,6 +77,7 @@ export class CommHandler extends PeerHandler<CommClientData> {
 		});
 
 		chat.find("#chatClose").click((_) => this.toggleChat());
+		chat.find("#addBot").click((_) => this.bot_callback?.());
 
 		input.keydown((e) => {
 			// If enter is pressed
@@ -164,6 +170,23 @@ export class CommHandler extends PeerHandler<CommClientData> {
 		this.set(client, this.setting.name, plr);
 	}
 
+	onstartmating() {
+		this.chatMessage(MessageType.Info, "Wählt eure Partner im Chat aus!");
+	}
+
+	onmaintenance(notice: string) {
+		this.chatMessage(MessageType.Info, `Wartungsarbeiten: ${notice}`);
+	}
+
+	onnotice(notice: string) {
+		this.chatMessage(MessageType.Info, `Mitteilung: ${notice}`);
+	}
+
+	onmate(client_id: ClientID) {
+		let name = this.clients.get(client_id)?.name;
+		if (name) this.chatMessage(MessageType.Info, `${name} möchte mit dir spielen.`);
+	}
+
 	onclient(data: ClientData, client_id: ClientID, player_id: PlayerID) {
 		super.onclient(data, client_id, player_id);
 
@@ -196,6 +219,18 @@ export class CommHandler extends PeerHandler<CommClientData> {
 			mutediv.append(box);
 			entry.append(mutediv);
 
+			let kick = $("<div>")
+				.addClass("ActionButton")
+				.text("Kick")
+				.click(() => this.kick_callback?.(client_id));
+			entry.append(kick);
+
+			let mate = $("<div>")
+				.addClass("ActionButton")
+				.text("Partner")
+				.click(() => this.mate_callback?.(client_id));
+			entry.append(mate);
+
 			this.chat.find("#playerSettings").append(entry);
 		}
 
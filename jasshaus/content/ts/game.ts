@@ -3,6 +3,7 @@ import {
 	Cardset,
 	Game,
 	Event as GameEvent,
+	game_version,
 	Playtype,
 	Show,
 	setting_molotow,
@@ -11,7 +12,7 @@ import { ClientData, ClientID, PlayerID, Wshandler } from "./wshandler.js";
 import { UI } from "./interface.js";
 import { get_pt_name } from "./jass.js";
 import { ClientSetting } from "./clientsetting.js";
-import { CommHandler } from "./chat.js";
+import { CommHandler, MessageType } from "./chat.js";
 import { VoteHandler } from "./voting.js";
 
 declare global {
@@ -44,15 +45,17 @@ export class Main {
 	constructor(addr: string, setting: ClientSetting) {
 		this.setting = setting;
 
-		this.wshandler = new Wshandler(addr, setting);
+		this.wshandler = new Wshandler(addr, setting, game_version());
 		this.comm = new CommHandler(setting);
 		this.vote = new VoteHandler($("body"), (idx) => this.wshandler.vote(idx));
 
 		// Join Events
 		this.wshandler.oninit = (c, p, n) => this.oninit(c, p, n);
+		this.wshandler.onspectate = (c, n) => this.onspectate(c, n);
 		this.wshandler.onclient = (d, cid, pid) => this.onclient(d, cid, pid);
 		this.wshandler.onclientleave = (cid) => this.onclientleave(cid);
 		this.wshandler.onevent = (d) => this.onevent(d);
+		this.wshandler.ongameerror = (e) => this.ongameerror(e);
 
 		// RTC events
 		this.wshandler.rtc_onstart = (cid) => this.comm.rtc_onstart(cid);
@@ -64,6 +67,17 @@ export class Main {
 		this.wshandler.onvote = (v, cid) => this.vote.onvote(v, cid);
 		this.wshandler.onnewvote = (ty) => this.vote.onnewvote(ty);
 		this.wshandler.onvotequit = () => this.vote.onvotequit();
+		this.comm.kick_callback = (cid) => this.wshandler.proposeKick(cid);
+		this.comm.bot_callback = () => this.wshandler.addBot();
+
+		// Team choosing
+		this.comm.mate_callback = (cid) => this.wshandler.mate(cid);
+		this.wshandler.onstartmating = () => this.comm.onstartmating();
+		this.wshandler.onmaintenance = (notice: string) => this.comm.onmaintenance(notice);
+		this.wshandler.onnotice = (notice: string) => this.comm.onnotice(notice);
+		this.wshandler.ondeadline = (d) => this.ui.players.ondeadline(d);
+		this.wshandler.onlatency = (d) => this.ui.players.onlatency(d);
+		this.wshandler.onmate = (cid) => this.comm.onmate(cid);
 
 		this.wshandler.onchatmessage = (msg, client_id) => {
 			let client = this.comm.get(client_id);
@@ -107,8 +121,14 @@ export class Main {
 		this.ui.updateName(this.player_id, this.setting.name);
 	}
 
+	// Spectators watch the table from the first player's seat
+	onspectate(client_id: ClientID, num_players: number) {
+		this.comm.oninit(client_id, this.player_id);
+		this.ui.players.oninit(client_id, this.player_id, num_players);
+	}
+
 	onclient(data: ClientData, client_id: ClientID, player_id: PlayerID) {
-		this.vote.onclient();
+		this.vote.onclient(data, client_id);
 		this.comm.onclient(data, client_id, player_id);
 		this.ui.players.onclient(data, client_id, player_id);
 		this.ui.updateName(player_id, data.name);
@@ -128,6 +148,17 @@ export class Main {
 		this.ui.players.onchatmessage(msg, client_id);
 	}
 
+	ongameerror(error: string) {
+		const messages: { [key: string]: string } = {
+			IllegalPlay: "Diese Karte darfst du nicht spielen.",
+			NotOnTurn: "Du bist nicht am Zug.",
+			WrongTime: "Das ist im Moment nicht möglich.",
+			IllegalAnnouncement: "Diese Ansage ist nicht erlaubt.",
+			IllegalShow: "Diesen Weis kannst du nicht weisen.",
+		};
+		this.comm.chatMessage(MessageType.Info, messages[error] ?? "Ungültiger Spielzug.");
+	}
+
 	// ---
 
 	onevent(event: any) {
@@ -10,33 +10,43 @@ import { get_card_ele, get_pt_name } from "./jass.js";
 import { add_custom_type, createForm } from "./formcreator.js";
 import { get_setting_form, save_jass_setting } from "./jasssettings.js";
 import { get_client_settings, getClientSettingForm, save_client_setting } from "./clientsetting.js";
-import { construct_fetch_url, enter_room, get_rooms, request_room } from "./roomfinder.js";
+import {
+	construct_fetch_url,
+	construct_lobby_url,
+	enter_room,
+	request_room,
+	watch_lobby,
+} from "./roomfinder.js";
 
 const URL = construct_fetch_url(7999);
+const LOBBY_URL = construct_lobby_url(7999);
 
-async function update_rooms() {
-	let rooms = undefined;
-
-	try {
-		rooms = await get_rooms(URL);
-	} catch (e) {
+function update_rooms() {
+	watch_lobby(LOBBY_URL, show_rooms, () => {
 		$("#rooms").text("Could not connect to server!").addClass("Error");
-		return;
-	}
+	});
+}
 
+function show_rooms(rooms: any[]) {
 	let eles = rooms.map((room: any) => {
 		let ele = $("<div>").addClass("Room");
 		let join = $("<button>")
 			.text("Beitreten")
-			.click(() => enter_room(room.id));
+			.click(() => {
+				if (!room.password) return enter_room(room.id);
+
+				let secret = prompt("Passwort des Raumes");
+				if (secret !== null) enter_room(room.id, secret);
+			});
 
 		let ratio = `${room.players.length}/${room.max_players}`;
 		let text = `Raum ${room.id} (${ratio})`;
 
 		let title = $("<h2>").text(text);
+		let summary = $("<p>").text(room.summary);
 		let names = $("<p>").text(room.players.join(", "));
 
-		ele.append(title).append(names).append($("<p>").append(join));
+		ele.append(title).append(summary).append(names).append($("<p>").append(join));
 
 		return ele;
 	});
@@ -153,11 +163,13 @@ window.onload = async () => {
 		if (result === "molotow") data = setting_molotow();
 		if (result.hasOwnProperty("custom")) data = result["custom"];
 
-		let response = await request_room(URL, data);
+		let is_private = $("#roomPrivate").is(":checked");
+		let password = String($("#roomPassword").val() ?? "");
+		let response = await request_room(URL, data, is_private, password);
 
 		if (response.status === 200) {
 			let room_id = await response.text();
-			enter_room(room_id);
+			enter_room(room_id, password);
 		} else {
 			$("#settingInfo").text(
 				"An error occured while creating a room. Have you entered valid data?",
@@ -166,8 +178,9 @@ window.onload = async () => {
 	});
 	$("#manualRoomEnter").click(() => {
 		let id = $("#roomInput").val();
+		let secret = String($("#roomSecret").val() ?? "");
 		if (typeof id === "string" || typeof id === "number") {
-			enter_room(id);
+			enter_room(id, secret);
 		}
 	});
 
@@ -5,6 +5,7 @@ export class CirclePlayer {
 	curplr: undefined | PlayerID = undefined;
 	players = new Map<ClientID, PlayerID>();
 	click_callback?: (plr: PlayerID) => void;
+	deadline_timer?: number;
 
 	setMessage(msg: string | JQuery<HTMLElement>, plr_id: PlayerID, delay: number = 6000) {
 		if (typeof msg === "string") msg = $("<span>").text(msg);
@@ -34,9 +35,36 @@ export class CirclePlayer {
 		$(`#wrapper_player${plr}`).append(ele);
 	}
 
+	// Show the remaining seconds of the player, who has to act
+	ondeadline(deadline: null | [PlayerID, number]) {
+		clearInterval(this.deadline_timer);
+		$(".Deadline").parent().remove();
+		if (!deadline) return;
+
+		let [plr_id, secs] = deadline;
+		let ele = $('<span class="Deadline">').text(`${secs}s`);
+		this.setMessage(ele, plr_id, 0);
+
+		this.deadline_timer = setInterval(() => {
+			secs = Math.max(0, secs - 1);
+			ele.text(`${secs}s`);
+		}, 1000);
+	}
+
+	// Show the round-trip time of every player next to their name
+	onlatency(latencies: [ClientID, number][]) {
+		for (let [client_id, ms] of latencies) {
+			let plr = this.players.get(client_id);
+			if (plr !== undefined) $(`#ping_player${plr}`).text(`${ms}ms`);
+		}
+	}
+
 	// ---
 
 	oninit(client_id: ClientID, player_id: PlayerID, num_players: number) {
+		// Seats may be rearranged after choosing teams
+		$("#pright, #pup, #pleft").remove();
+
 		$("body")
 			.append($("<div>").attr("id", "pright"))
 			.append($("<div>").attr("id", "pup"))
@@ -54,7 +82,9 @@ export class CirclePlayer {
 				.addClass("Player")
 				.click(() => this.click_callback?.(id));
 
-			let indicators = $('<div class="IndicatorRow">');
+			let indicators = $('<div class="IndicatorRow">').append(
+				$('<span class="Ping">').attr("id", `ping_player${id}`),
+			);
 
 			container.append(name).append(indicators);
 
@@ -83,6 +113,7 @@ export class CirclePlayer {
 
 		this.players.delete(client_id);
 		$(`#player${plr}`).text("");
+		$(`#ping_player${plr}`).text("");
 	}
 
 	onchatmessage(msg: string, client_id: ClientID) {
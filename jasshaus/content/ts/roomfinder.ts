@@ -5,23 +5,69 @@ export function construct_fetch_url(port: number) {
 	return `${location.origin}/rooms`;
 }
 
-export function enter_room(id: number | string) {
+export function construct_lobby_url(port: number) {
+	return construct_fetch_url(port).replace(/^http/, "ws").replace(/\/rooms$/, "/lobby");
+}
+
+export function enter_room(id: number | string, secret?: string) {
+	// Kept out of the url, so the password doesn't end up in the history
+	if (secret) sessionStorage.setItem(`secret:${id}`, secret);
 	window.location.assign(`game.html?room=${id}`);
 }
 
-export async function request_room(url: string, data: any): Promise<Response> {
+export async function request_room(
+	url: string,
+	data: any,
+	is_private: boolean = false,
+	password: string = "",
+): Promise<Response> {
 	let response = await fetch(url, {
 		method: "POST",
-		body: JSON.stringify(data),
+		body: JSON.stringify({
+			setting: data,
+			private: is_private,
+			password: password || null,
+		}),
 	});
 
 	return response;
 }
 
-export async function get_rooms(url: string) {
-	let response = await fetch(url, {
-		method: "GET",
-	});
+// Delay before reconnecting to the lobby, doubled after every failed attempt
+const LOBBY_RETRY_MS = 1000;
+const MAX_LOBBY_RETRY_MS = 30000;
+
+// Keep the list of joinable rooms current with the changes pushed by the lobby,
+// onerror is called whenever the connection is lost, before trying again
+export function watch_lobby(url: string, onrooms: (rooms: any[]) => void, onerror: () => void) {
+	let rooms = new Map<string, any>();
+	let delay = LOBBY_RETRY_MS;
+
+	let connect = () => {
+		let socket = new WebSocket(url);
+		socket.onopen = () => (delay = LOBBY_RETRY_MS);
+		socket.onmessage = (e) => {
+			let obj = JSON.parse(e.data);
+
+			// Sent after every (re)connect, replaces everything known before
+			if ("Rooms" in obj) {
+				rooms.clear();
+				for (let room of obj.Rooms) rooms.set(room.id, room);
+			}
+			if ("RoomCreated" in obj) rooms.set(obj.RoomCreated.id, obj.RoomCreated);
+			if ("RoomUpdated" in obj) rooms.set(obj.RoomUpdated.id, obj.RoomUpdated);
+			if ("RoomClosed" in obj) rooms.delete(obj.RoomClosed);
+
+			let joinable = [...rooms.values()].filter((room) => room.free_seats > 0);
+			joinable.sort((a, b) => a.created - b.created);
+			onrooms(joinable);
+		};
+		socket.onclose = () => {
+			onerror();
+			setTimeout(connect, delay);
+			delay = Math.min(2 * delay, MAX_LOBBY_RETRY_MS);
+		};
+	};
 
-	return await response.json();
+	connect();
 }
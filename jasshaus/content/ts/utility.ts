@@ -32,6 +32,11 @@ function getRoomID(): null | string {
 }
 export const ROOM_ID = getRoomID();
 
+function getRoomSecret(): null | string {
+	return sessionStorage.getItem(`secret:${ROOM_ID}`);
+}
+export const ROOM_SECRET = getRoomSecret();
+
 export function determine_ws_url(port: number): string {
 	if (DEV_MODE) {
 		if (location.protocol == "http:") {
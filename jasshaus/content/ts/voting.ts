@@ -1,4 +1,4 @@
-import { ClientID } from "./wshandler";
+import { ClientData, ClientID } from "./wshandler";
 
 class Option {
 	name: string;
@@ -93,6 +93,8 @@ class VoteManager {
 
 export class VoteHandler {
 	num_clients: number = 1;
+	// Bots don't vote
+	bots = new Set<ClientID>();
 	container: JQuery<HTMLElement>;
 
 	vote?: VoteManager = undefined;
@@ -105,12 +107,19 @@ export class VoteHandler {
 		this.callback = callback;
 	}
 
-	onclient() {
+	onclient(data: ClientData, client_id: ClientID) {
+		if (data.bot) {
+			this.bots.add(client_id);
+			return;
+		}
+
 		this.num_clients += 1;
 		if (this.vote) this.vote.setTotal(this.num_clients);
 	}
 
 	onclientleave(client_id: ClientID) {
+		if (this.bots.delete(client_id)) return;
+
 		this.num_clients -= 1;
 		if (this.vote) this.vote.onClientQuit(client_id);
 	}
@@ -120,12 +129,21 @@ export class VoteHandler {
 		this.vote.agreeTo(vote, false, client_id);
 	}
 
-	onnewvote(type: string) {
+	onnewvote(type: any) {
 		let opts: string[] = [];
+		let text: string = type;
+		let total = this.num_clients;
+
+		if (type == "Revanche" || type == "StartGame") opts = ["Ja", "Nein"];
+		if (type == "Teaming") opts = ["Fertig"];
+		if (typeof type === "object" && "Kick" in type) {
+			// The kicked client can't vote
+			text = "Kick";
+			opts = ["Ja", "Nein"];
+			if (!this.bots.has(type.Kick)) total -= 1;
+		}
 
-		if (type == "Revanche") opts = ["Ja", "Nein"];
-
-		this.vote = new VoteManager(type, this.num_clients, opts, $("body"), this.callback);
+		this.vote = new VoteManager(text, total, opts, $("body"), this.callback);
 	}
 
 	onvotequit() {
@@ -1,30 +1,65 @@
 // import { CommHandler } from "./chat.js";
 
 import { ClientSetting } from "./clientsetting";
+import { ROOM_SECRET } from "./utility.js";
 
 export type ClientID = number;
 export type PlayerID = number;
 
 export type ClientData = {
 	name: string;
+	bot?: boolean;
+};
+
+// Has to match the protocol version of the server
+const PROTOCOL_VERSION = 1;
+// Set while reloading for a new version, so an outdated cache can't reload the page forever
+const UPGRADE_RELOAD_KEY = "upgrade-reload";
+
+// Close codes sent by the server, when it refuses or drops the connection
+const CLOSE_REASONS: { [code: number]: string } = {
+	4000: "Zeitüberschreitung beim Verbindungsaufbau.",
+	4001: "Inkompatible Version, bitte lade die Seite neu.",
+	4002: "Dieser Raum existiert nicht.",
+	4003: "Dieser Raum ist voll.",
+	4004: "Bitte gib einen Namen an.",
+	4005: "Du wurdest aus diesem Raum geworfen.",
+	4006: "Falsches Passwort.",
+	4007: "Zu viele oder zu lange Nachrichten.",
+	4008: "Es gibt eine neue Version, die Seite wird neu geladen.",
 };
 
 export class Wshandler {
 	socket: WebSocket;
+	session_key: string;
+
+	client_id: ClientID = 0;
+	num_players: number = 0;
+	clients = new Map<ClientID, ClientData>();
 
 	oninit?: (client: ClientID, plr: PlayerID, num_players: number) => void;
+	onspectate?: (client: ClientID, num_players: number) => void;
 
 	onclient?: (data: ClientData, client_id: ClientID, player_id: PlayerID) => void;
 	onclientleave?: (client_id: ClientID) => void;
+	onspectator?: (data: ClientData, client_id: ClientID) => void;
 
 	onchatmessage?: (msg: string, client: ClientID) => void;
 
 	onevent?: (event: any) => void;
+	ongameerror?: (error: any) => void;
 
 	onvote?: (vote: number, client: ClientID) => void;
-	onnewvote?: (type: string) => void;
+	onnewvote?: (type: any) => void;
 	onvotequit?: () => void;
 
+	onstartmating?: () => void;
+	onmate?: (client_id: ClientID) => void;
+	onmaintenance?: (notice: string) => void;
+	onnotice?: (notice: string) => void;
+	ondeadline?: (deadline: null | [PlayerID, number]) => void;
+	onlatency?: (latencies: [ClientID, number][]) => void;
+
 	rtc_onstart?: (client_id: ClientID) => Promise<undefined | RTCSessionDescription>;
 	rtc_onoffer?: (
 		client_id: ClientID,
@@ -33,13 +68,17 @@ export class Wshandler {
 	rtc_onanswer?: (client_id: ClientID, answer: RTCSessionDescription) => Promise<void>;
 	rtc_onicecandidate?: (client_id: ClientID, candidate: RTCIceCandidate) => Promise<void>;
 
-	constructor(addr: string, setting: ClientSetting) {
+	constructor(addr: string, setting: ClientSetting, game_version: number) {
 		this.socket = new WebSocket(addr);
+		this.session_key = "session:" + addr;
 
 		this.socket.onopen = async (_) => {
 			this.send({
 				Introduction: {
 					name: setting.name,
+					session: sessionStorage.getItem(this.session_key),
+					secret: ROOM_SECRET,
+					version: { protocol: PROTOCOL_VERSION, game: game_version },
 				},
 			});
 			if (setting.allow_rtc) this.rtc_start();
@@ -54,12 +93,22 @@ export class Wshandler {
 
 			let data = obj[head];
 
+			// Messages without data, like the heartbeat, are sent as bare strings
 			if (typeof obj === "string") {
+				if ("Ping" === obj) this.Ping();
 				if ("QuitVote" === obj) this.QuitVote();
+				if ("StartMating" === obj) this.onstartmating?.();
 				return;
 			}
 
+			// The server accepted our version
+			if ("PlayerID" in obj || "SpectatorID" in obj) {
+				sessionStorage.removeItem(UPGRADE_RELOAD_KEY);
+			}
 			if ("PlayerID" in obj) this.PlayerID(data);
+			if ("SpectatorID" in obj) this.SpectatorID(data);
+			if ("SpectatorJoined" in obj) this.SpectatorJoined(data);
+			if ("JoinedSpectators" in obj) this.JoinedSpectators(data);
 			if ("ClientJoined" in obj) this.ClientJoined(data);
 			if ("ClientDisconnected" in obj) this.ClientDisconnected(data);
 			if ("JoinedClients" in obj) this.JoinedClients(data);
@@ -69,15 +118,38 @@ export class Wshandler {
 			if ("Vote" in obj) this.HandleVote(data);
 			if ("CurrentVote" in obj) this.CurrentVote(data);
 			if ("NewVote" in obj) this.HandleNewVote(data);
-			if ("Ping" in obj) this.Ping(data);
+			if ("PlayerOrder" in obj) this.PlayerOrder(data);
+			if ("Mate" in obj) this.Mate(data);
+			if ("Maintenance" in obj) this.onmaintenance?.(data);
+			if ("Notice" in obj) this.onnotice?.(data);
+			if ("TurnDeadline" in obj) this.ondeadline?.(data);
+			if ("Latency" in obj) this.onlatency?.(data);
 			if ("Event" in obj) this.Event(data);
+			if ("Error" in obj) this.ongameerror?.(data);
 		};
 
 		this.socket.onerror = async (_) => {
 			alert("Ein Fehler während der Verbindung des Servers ist aufgetreten!");
 		};
 
-		this.socket.onclose = (_) => {
+		this.socket.onclose = (e) => {
+			if (e.code == 4008 && sessionStorage.getItem(UPGRADE_RELOAD_KEY)) {
+				sessionStorage.removeItem(UPGRADE_RELOAD_KEY);
+				alert(
+					"Die Seite ist immer noch veraltet. Bitte leere den Cache des Browsers und lade die Seite neu.",
+				);
+				return;
+			}
+
+			let reason = CLOSE_REASONS[e.code];
+			if (reason) {
+				alert(`Die Verbindung zum Server wurde geschlossen: ${reason}`);
+				if (e.code == 4008) {
+					sessionStorage.setItem(UPGRADE_RELOAD_KEY, "1");
+					location.reload();
+				}
+				return;
+			}
 			alert("Die Verbindung zum Server wurde geschlossen!");
 		};
 	}
@@ -104,27 +176,62 @@ export class Wshandler {
 	// ===== Methods Handler =====
 
 	PlayerID(data: any) {
-		let [client_id, player_id, num_players]: [ClientID, PlayerID, number] = data;
+		let [client_id, player_id, num_players, session]: [ClientID, PlayerID, number, string] =
+			data;
+		sessionStorage.setItem(this.session_key, session);
+
+		this.client_id = client_id;
+		this.num_players = num_players;
 		this.oninit?.(client_id, player_id, num_players);
 	}
 
+	SpectatorID([client_id, num_players]: [ClientID, number]) {
+		this.onspectate?.(client_id, num_players);
+	}
+
 	// -----
 
 	ClientJoined([data, client_id, player_id]: [ClientData, ClientID, PlayerID]) {
+		this.clients.set(client_id, data);
 		this.onclient?.(data, client_id, player_id);
 	}
 
+	SpectatorJoined([data, client_id]: [ClientData, ClientID]) {
+		this.onspectator?.(data, client_id);
+	}
+
+	JoinedSpectators(list: [ClientData, ClientID][]) {
+		for (let entry of list) this.SpectatorJoined(entry);
+	}
+
 	ClientDisconnected(client_id: number) {
+		this.clients.delete(client_id);
 		this.onclientleave?.(client_id);
 	}
 
 	JoinedClients(list: any[]) {
 		for (let entry of list) {
 			let [data, client_id, player_id]: [ClientData, ClientID, PlayerID] = entry;
-			this.onclient?.(data, client_id, player_id);
+			this.ClientJoined([data, client_id, player_id]);
+		}
+	}
+
+	/// The seats have been rearranged, so introduce everyone again
+	PlayerOrder(order: [ClientID, PlayerID][]) {
+		for (let [client_id, player_id] of order) {
+			if (client_id == this.client_id) this.oninit?.(client_id, player_id, this.num_players);
+		}
+
+		for (let [client_id, player_id] of order) {
+			let data = this.clients.get(client_id);
+			if (data) this.onclient?.(data, client_id, player_id);
 		}
 	}
 
+	Mate(client_id: ClientID) {
+		this.onmate?.(client_id);
+	}
+
 	// -----
 
 	Event(event: any) {
@@ -174,6 +281,19 @@ export class Wshandler {
 		this.send({ Vote: [index, 0] });
 	}
 
+	proposeKick(client_id: ClientID) {
+		this.send({ NewVote: { Kick: client_id } });
+	}
+
+	mate(client_id: ClientID) {
+		this.send({ Mate: client_id });
+	}
+
+	// Only the host can add bots, before the game starts
+	addBot() {
+		this.send("AddBot");
+	}
+
 	sendChatmessage(msg: string) {
 		this.send({ ChatMessage: [msg, 0] });
 	}
@@ -190,12 +310,12 @@ export class Wshandler {
 		this.onvote?.(opt, cid);
 	}
 
-	HandleNewVote(ty: string) {
+	HandleNewVote(ty: any) {
 		this.onnewvote?.(ty);
 	}
 
 	CurrentVote(data: any) {
-		let [votetype, votes]: [string, any] = data;
+		let [votetype, votes]: [any, any] = data;
 
 		this.HandleNewVote(votetype);
 		for (let vote of votes) this.HandleVote(vote);
@@ -207,7 +327,7 @@ export class Wshandler {
 
 	// ---
 
-	Ping(_: any) {
+	Ping() {
 		this.send("Pong");
 	}
 }
@@ -0,0 +1,70 @@
+use game_server::room::bot::Bot;
+use rand::prelude::SliceRandom;
+
+use crate::server::JassRoom;
+use crate::*;
+use Event::*;
+
+/// What a bot or an idle player bids, when its team has no target yet.
+/// About half of the points of a round.
+const BOT_BID: i32 = 78;
+
+/// A random playtype, which the current player may announce
+pub fn random_announcement(game: &Game) -> Option<Playtype> {
+	let legal: Vec<Playtype> = (0..game.setting.playtype.len())
+		.filter_map(Playtype::from_id)
+		.filter(|&pt| game.legal_announcement(pt, false, None))
+		.collect();
+
+	let mut rng = rand::thread_rng();
+	legal.choose(&mut rng).copied()
+}
+
+/// Keeps the target of the team, if it has one
+pub fn holding_bid(game: &Game, plr_id: usize) -> i32 {
+	let team = game.players[plr_id].team_id;
+	game.teams[team].target.unwrap_or(BOT_BID)
+}
+
+/// A random card of the hand, which is legal to play
+pub fn random_card(game: &Game, hand: &Cardset) -> Option<Card> {
+	let legal: Vec<Card> = hand
+		.as_vec()
+		.into_iter()
+		.filter(|&card| game.is_legal_card(hand, card))
+		.collect();
+
+	let mut rng = rand::thread_rng();
+	legal.choose(&mut rng).copied()
+}
+
+/// Plays random legal moves
+#[derive(Default)]
+pub struct JassBot;
+
+impl Bot<JassRoom, Event> for JassBot {
+	fn act(&mut self, room: &JassRoom, plr_id: usize) -> Option<Event> {
+		let game = &room.game;
+		if game.should_end() {
+			return None;
+		}
+
+		if game.can_bid(plr_id) {
+			return Some(Bid(holding_bid(game, plr_id)));
+		}
+
+		if game.can_announce(plr_id) {
+			return match random_announcement(game) {
+				Some(pt) => Some(Announce(pt, false)),
+				None if game.can_pass(plr_id) => Some(Pass),
+				None => None,
+			};
+		}
+
+		if game.is_playing() && game.current_player == plr_id {
+			return random_card(game, &game.players[plr_id].hand).map(PlayCard);
+		}
+
+		None
+	}
+}
@@ -1,3 +1,5 @@
+#[cfg(feature = "server")]
+pub mod bot;
 pub mod card;
 pub mod ruleset;
 #[cfg(feature = "server")]
@@ -14,6 +16,14 @@ use serde::{Deserialize, Serialize};
 use tsify_next::Tsify;
 use wasm_bindgen::prelude::*;
 
+/// Version of the game protocol, increase it on every incompatible change of `Event`
+pub const GAME_VERSION: u32 = 1;
+
+#[wasm_bindgen]
+pub fn game_version() -> u32 {
+	GAME_VERSION
+}
+
 #[derive(Tsify)]
 #[tsify(into_wasm_abi, from_wasm_abi)]
 #[derive(Clone, PartialEq, std::fmt::Debug, Serialize, Deserialize)]
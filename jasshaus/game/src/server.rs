@@ -4,14 +4,22 @@ use game_server::socket_message::SocketMessage;
 use rand::prelude::SliceRandom;
 use rand::Rng;
 
+use crate::bot::*;
 use crate::*;
-use game_server::room::{client::*, *};
+use game_server::room::{bot::Bot, client::*, *};
 use Event::*;
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, std::fmt::Debug, Serialize, Deserialize)]
 pub enum GameError {
+	/// The card can't be played on the current trick
 	IllegalPlay,
 	NotOnTurn,
+	/// The action isn't possible at this point of the game
+	WrongTime,
+	/// The playtype can't be announced or passing isn't allowed
+	IllegalAnnouncement,
+	/// The player doesn't have the show or already showed it
+	IllegalShow,
 }
 
 #[derive(Clone, Copy, PartialEq, std::fmt::Debug, Serialize, Deserialize)]
@@ -20,11 +28,11 @@ pub enum RoundState {
 	Playing,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct JassRoom {
 	starts: u32,
 	roundstate: RoundState,
-	game: Game,
+	pub(crate) game: Game,
 }
 
 impl TryFrom<Setting> for JassRoom {
@@ -61,6 +69,32 @@ impl JassRoom {
 		}
 	}
 
+	/// Pass or announce a random playtype for the player
+	async fn announce_for(&mut self, clients: &mut ClientHandler, plr_id: usize) {
+		if self.game.can_pass(plr_id) {
+			let _ = self.pass(clients, plr_id).await;
+			return;
+		}
+
+		if let Some(pt) = random_announcement(&self.game) {
+			let _ = self.announce(clients, pt, false, plr_id, None).await;
+		}
+	}
+
+	/// Bid for the player, without raising the target of the team
+	async fn bid_for(&mut self, clients: &mut ClientHandler, plr_id: usize) {
+		let bid = holding_bid(&self.game, plr_id);
+		let _ = self.bid(clients, bid, plr_id).await;
+	}
+
+	/// Play a random legal card for the player
+	async fn play_for(&mut self, clients: &mut ClientHandler, plr_id: usize) {
+		let hand = self.game.players[plr_id].hand;
+		if let Some(card) = random_card(&self.game, &hand) {
+			let _ = self.play_card(clients, card, plr_id).await;
+		}
+	}
+
 	async fn start_round(&mut self, clients: &mut ClientHandler) {
         let cards = {
 			let mut cards = all_cards();
@@ -123,23 +157,28 @@ impl JassRoom {
 		}
 	}
 
-	async fn play_card(&mut self, clients: &mut ClientHandler, card: Card, plr_id: usize) {
+	async fn play_card(
+		&mut self,
+		clients: &mut ClientHandler,
+		card: Card,
+		plr_id: usize,
+	) -> Result<(), GameError> {
 		if self.roundstate != RoundState::Playing {
-			return;
+			return Err(GameError::WrongTime);
 		}
 
 		// Check for basic cheating
 		if !self.game.is_playing() {
-			return;
+			return Err(GameError::WrongTime);
 		}
 		if self.game.current_player != plr_id {
-			return;
+			return Err(GameError::NotOnTurn);
 		}
 		if !self
 			.game
 			.is_legal_card(&self.game.players[plr_id].hand, card)
 		{
-			return;
+			return Err(GameError::IllegalPlay);
 		}
 
 		// Handle shows
@@ -199,6 +238,8 @@ impl JassRoom {
 			self.game.update_round_results();
 			self.start_round(clients).await;
 		}
+
+		Ok(())
 	}
 
 	async fn handle_announce(&mut self, clients: &mut ClientHandler, pt: Playtype, misere: bool) {
@@ -222,47 +263,73 @@ impl JassRoom {
 		misere: bool,
 		plr_id: usize,
         joker: Option<usize>
-	) {
-		if self.game.can_announce(plr_id) && self.game.legal_announcement(pt, misere, joker) {
-			self.handle_announce(clients, pt, misere).await;
+	) -> Result<(), GameError> {
+		if !self.game.can_announce(plr_id) {
+			return Err(GameError::NotOnTurn);
+		}
+		if !self.game.legal_announcement(pt, misere, joker) {
+			return Err(GameError::IllegalAnnouncement);
 		}
+
+		self.handle_announce(clients, pt, misere).await;
+		Ok(())
 	}
 
-	async fn pass(&mut self, clients: &mut ClientHandler, plr_id: usize) {
-		if self.game.can_pass(plr_id) {
-			self.game.pass();
-			clients.ev_send_to_all(Pass).await;
+	async fn pass(&mut self, clients: &mut ClientHandler, plr_id: usize) -> Result<(), GameError> {
+		if !self.game.can_pass(plr_id) {
+			return Err(GameError::IllegalAnnouncement);
 		}
+
+		self.game.pass();
+		clients.ev_send_to_all(Pass).await;
+		Ok(())
 	}
 
-	async fn play_show(&mut self, _clients: &mut ClientHandler, show: Show, plr_id: usize) {
+	async fn play_show(
+		&mut self,
+		_clients: &mut ClientHandler,
+		show: Show,
+		plr_id: usize,
+	) -> Result<(), GameError> {
 		if !self.game.can_show(plr_id) {
-			return;
+			return Err(GameError::WrongTime);
 		}
 		if let Err(_) = self.game.players[plr_id].hand.has_show(show) {
-			return;
+			return Err(GameError::IllegalShow);
 		}
 		if self.game.players[plr_id].shows.iter().any(|s| *s == show) {
-			return;
+			return Err(GameError::IllegalShow);
 		}
 
 		self.game.play_show(show, plr_id);
+		Ok(())
 	}
 
-	async fn bid(&mut self, clients: &mut ClientHandler, bid: i32, plr_id: usize) {
+	async fn bid(
+		&mut self,
+		clients: &mut ClientHandler,
+		bid: i32,
+		plr_id: usize,
+	) -> Result<(), GameError> {
 		if !self.game.can_bid(plr_id) {
-			return;
+			return Err(GameError::NotOnTurn);
 		}
 		self.game.bid(bid);
 		clients.ev_send_to_all(Bid(bid)).await;
+		Ok(())
 	}
 }
 
 #[async_trait]
 impl ServerRoom<Event> for JassRoom {
 	type Err = GameError;
+	const GAME_VERSION: u32 = crate::GAME_VERSION;
 
-	async fn start(&mut self, clients: &mut ClientHandler) -> Result<(), Self::Err> {
+	async fn start(
+		&mut self,
+		clients: &mut ClientHandler,
+		_num_players: usize,
+	) -> Result<(), Self::Err> {
 		let ranking = self.game.rank_teams();
 		self.game = Game::new(self.game.setting.clone());
 
@@ -305,6 +372,18 @@ impl ServerRoom<Event> for JassRoom {
 	}
 	async fn on_leave(&mut self, _clients: &mut ClientHandler, _plr_id: usize) {}
 
+	async fn on_spectate(&mut self, clients: &mut ClientHandler, client_id: usize) {
+		if self.starts > 0 {
+			let game = self.game.public_clone();
+			clients
+				.ev_send_to_client(client_id, GameState(game, Cardset::default(), vec![]))
+				.await;
+		} else {
+			let setting = self.game.setting.clone();
+			clients.ev_send_to_client(client_id, GameSetting(setting)).await;
+		}
+	}
+
 	async fn on_event(
 		&mut self,
 		clients: &mut ClientHandler,
@@ -312,26 +391,228 @@ impl ServerRoom<Event> for JassRoom {
 		plr_id: usize,
 	) -> Result<(), Self::Err> {
 		if self.starts == 0 {
-			return Ok(());
+			return Err(GameError::WrongTime);
 		}
 
 		match event {
-			PlayCard(card) => self.play_card(clients, card, plr_id).await,
-			Announce(pt, misere) => self.announce(clients, pt, misere, plr_id, None).await,
-            JokerAnnounce(pt, misere, joker) => self.announce(clients, pt, misere, plr_id, Some(joker)).await,
-	        Pass => self.pass(clients, plr_id).await,
-			PlayShow(show) => self.play_show(clients, show, plr_id).await,
-			Bid(bid) => self.bid(clients, bid, plr_id).await,
+			PlayCard(card) => self.play_card(clients, card, plr_id).await?,
+			Announce(pt, misere) => self.announce(clients, pt, misere, plr_id, None).await?,
+            JokerAnnounce(pt, misere, joker) => self.announce(clients, pt, misere, plr_id, Some(joker)).await?,
+	        Pass => self.pass(clients, plr_id).await?,
+			PlayShow(show) => self.play_show(clients, show, plr_id).await?,
+			Bid(bid) => self.bid(clients, bid, plr_id).await?,
 			_ => {}
 		}
+
 		Ok(())
 	}
 
+	/// The player, who has to announce, to bid or to play a card
+	fn on_turn(&self) -> Option<usize> {
+		if self.starts == 0 || self.game.should_end() {
+			return None;
+		}
+		if self.game.is_biding() {
+			return Some(self.game.current_player);
+		}
+
+		match self.roundstate {
+			RoundState::Starting if !self.game.is_announced() => {
+				Some(self.game.get_announcing_player())
+			}
+			RoundState::Playing if self.game.is_playing() => Some(self.game.current_player),
+			_ => None,
+		}
+	}
+
+	fn ends_turn(&self, event: &Event) -> bool {
+		// Showing is part of playing the first card
+		!matches!(event, PlayShow(_))
+	}
+
+	async fn on_timeout(&mut self, clients: &mut ClientHandler, plr_id: usize) {
+		if self.game.can_bid(plr_id) {
+			self.bid_for(clients, plr_id).await;
+			return;
+		}
+
+		match self.roundstate {
+			RoundState::Starting => self.announce_for(clients, plr_id).await,
+			RoundState::Playing => self.play_for(clients, plr_id).await,
+		}
+	}
+
+	fn new_bot(&self) -> Option<Box<dyn Bot<Self, Event>>> {
+		Some(Box::new(JassBot))
+	}
+
+	fn summary(&self) -> String {
+		match self.game.setting.end_condition {
+			setting::EndCondition::Points(points) => format!("Auf {} Punkte", points),
+			setting::EndCondition::Rounds(rounds) => format!("{} Runden", rounds),
+			setting::EndCondition::None => String::from("Ohne Ziel"),
+		}
+	}
+
 	fn get_player_bound(&self) -> (usize, usize) {
+		// Every seat has to be taken, so the room never votes to start early
 		let n = self.game.setting.num_players;
 		(n, n)
 	}
+	fn get_teams(&self) -> Vec<Vec<usize>> {
+		let mut teams = vec![vec![]; self.game.teams.len()];
+		for (plr_id, plr) in self.game.players.iter().enumerate() {
+			teams[plr.team_id].push(plr_id);
+		}
+		teams
+	}
 	fn should_end(&self) -> bool {
 		self.game.should_end()
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::card::NUM_CARDS;
+	use crate::setting::setting_schieber;
+	use game_server::socket_message::{ClientData, ErrorMessage};
+	use game_server::transport::{ChannelTransport, Message};
+	use std::time::Duration;
+	use tokio::sync::mpsc::UnboundedReceiver;
+
+	type TestRoom = Room<Setting, Event, JassRoom>;
+	type Inbox = UnboundedReceiver<Message>;
+
+	/// A room, in which four clients connected over channels started a game
+	async fn started_room(game_setting: Setting) -> (TestRoom, Vec<(usize, Inbox)>) {
+		let setting = RoomSetting {
+			public: false,
+			password: None,
+			game_setting,
+		};
+		let mut room = TestRoom::try_new(RoomID::from("TEST"), setting).unwrap();
+
+		let mut clients = vec![];
+		for name in ["Alice", "Bob", "Carol", "Dave"] {
+			let data: ClientData = serde_json::from_str(&format!("{{\"name\": \"{}\"}}", name)).unwrap();
+			let (transport, inbox) = ChannelTransport::new();
+			let (_, id) = room.register(data, Box::new(transport)).await;
+			clients.push((id, inbox));
+		}
+
+		// Nobody chooses a partner
+		for &(id, _) in clients.iter() {
+			room.handle_input(SocketMessage::Vote(0, id), id).await;
+		}
+		assert_eq!(room.state, RoomState::Playing);
+
+		(room, clients)
+	}
+
+	/// The game events a client received since the last call, none of its events may have been rejected
+	async fn events(inbox: &mut Inbox) -> Vec<Event> {
+		// Every client has its own writer task, let them catch up
+		tokio::task::yield_now().await;
+
+		let mut events = vec![];
+		while let Ok(msg) = inbox.try_recv() {
+			let Message::Text(text) = msg else { continue };
+			assert!(!text.as_str().starts_with("{\"Error\""), "{}", text.as_str());
+
+			if let Ok(SocketMessage::Event(event)) = serde_json::from_str(text.as_str()) {
+				events.push(event);
+			}
+		}
+		events
+	}
+
+	#[tokio::test]
+	async fn rejected_events_are_answered_with_the_error() {
+		let (mut room, mut clients) = started_room(setting_schieber()).await;
+		let (id, inbox) = &mut clients[0];
+		events(inbox).await;
+
+		// Nothing can be played before the playtype is announced
+		let plr_id = room.clients[id].player_id;
+		let card = room.game.game.players[plr_id].hand.as_vec()[0];
+		room.handle_input(SocketMessage::Event(PlayCard(card)), *id).await;
+		tokio::task::yield_now().await;
+
+		let Ok(Message::Text(text)) = inbox.try_recv() else {
+			panic!("Nothing was answered");
+		};
+		let answer: ErrorMessage<GameError> = serde_json::from_str(text.as_str()).unwrap();
+		assert_eq!(answer, ErrorMessage::Error(GameError::WrongTime));
+	}
+
+	#[tokio::test]
+	async fn a_round_is_played_through_the_room() {
+		let (mut room, mut clients) = started_room(setting_schieber()).await;
+
+		// Everyone only sees their own hand
+		for (id, inbox) in clients.iter_mut() {
+			let hand = room.game.game.players[room.clients[id].player_id].hand;
+			let hands: Vec<Cardset> = events(inbox).await
+				.into_iter()
+				.filter_map(|event| match event {
+					NewCards(cards) => Some(cards),
+					_ => None,
+				})
+				.collect();
+			assert_eq!(hands, vec![hand]);
+		}
+
+		// The clients make the moves of a bot, until every card is played
+		let mut played = 0;
+		while played < NUM_CARDS {
+			let (id, event) = clients
+				.iter()
+				.find_map(|(id, _)| {
+					let plr_id = room.clients[id].player_id;
+					JassBot.act(&room.game, plr_id).map(|event| (*id, event))
+				})
+				.expect("Nobody can act");
+
+			played += matches!(event, PlayCard(_)) as usize;
+			room.handle_input(SocketMessage::Event(event), id).await;
+		}
+
+		for (_, inbox) in clients.iter_mut() {
+			let cards = events(inbox).await
+				.into_iter()
+				.filter(|event| matches!(event, PlayCard(_)))
+				.count();
+			assert_eq!(cards, NUM_CARDS);
+		}
+	}
+	#[tokio::test]
+	async fn idle_players_do_not_stall_the_game() {
+		let setting = Setting {
+			point_eval: PointEval::Difference {
+				include_shows: false,
+				include_marriage: false,
+				zero_diff_points: 0,
+				needs_win: false,
+			},
+			..setting_schieber()
+		};
+		let (mut room, mut clients) = started_room(setting).await;
+		let inbox = &mut clients[0].1;
+
+		// Announcing, bidding and playing all run out of time
+		let mut played = 0;
+		let mut bids = 0;
+		while played < NUM_CARDS {
+			let plr_id = room.deadline.expect("Nobody is on turn").plr_id;
+			room.deadline = Some(Deadline::new(plr_id, Duration::ZERO));
+			room.tick().await;
+
+			for event in events(inbox).await {
+				played += matches!(event, PlayCard(_)) as usize;
+				bids += matches!(event, Bid(_)) as usize;
+			}
+		}
+		assert!(0 < bids);
+	}
+}
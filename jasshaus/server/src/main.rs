@@ -5,6 +5,7 @@ use jasshaus_game::{server::*, setting::*, Event};
 async fn main() {
     Server::new("0.0.0.0:7999", "jasshaus")
 		.unix_socket("/tmp/jasshaus_sock")
+		.snapshot_dir("/var/lib/jasshaus")
 		.build::<Setting, Event, JassRoom>()
 		.await;
 }
@@ -1,16 +1,31 @@
 use clap::{Parser, Subcommand};
 
 use std::error::Error;
-use std::io::prelude::*;
-use std::os::unix::net::UnixStream;
 use std::path::PathBuf;
 
-use game_server::room::*;
+use game_server::admin::AdminClient;
+use game_server::room::{watch::*, *};
 
 #[derive(Subcommand)]
 enum Commands {
-	Close { room_id: RoomID },
+	/// List every room with its players
 	List,
+	/// Show the players, state, votes and settings of a room
+	Inspect { room_id: RoomID },
+	/// Close a room and disconnect its clients
+	Close { room_id: RoomID },
+	/// Remove a client from a room, players are banned for a while
+	Kick { room_id: RoomID, client_id: usize },
+	/// Show a notice to every client in every room
+	Broadcast { notice: String },
+	/// Close the rooms, which nobody uses anymore
+	Maintain,
+	/// Save a snapshot of all rooms to a file on the server
+	Save { path: String },
+	/// Restore the rooms from a snapshot file on the server
+	Load { path: String },
+	/// Print every message passing through a room, until interrupted
+	Watch { room_id: RoomID },
 }
 
 #[derive(Parser)]
@@ -20,51 +35,173 @@ struct Cli {
 
 	#[arg(long, default_value_t = String::from("default"))]
 	server: String,
+
+	/// Print the answer as JSON
+	#[arg(long)]
+	json: bool,
 }
 
 use ServerAnswer::*;
 use ServerRequest::*;
 
+fn print_clients(title: &str, clients: &[ClientInfo]) {
+	println!("{}:", title);
+	for client in clients {
+		let seat = match client.player_id {
+			Some(plr) => format!("seat {}", plr),
+			None => String::from("spectating"),
+		};
+		let bot = if client.bot { ", bot" } else { "" };
+		let ping = match client.latency_ms {
+			Some(ms) => format!(", {}ms", ms),
+			None => String::new(),
+		};
+		let vote = match client.vote {
+			Some(vote) => format!(", voted {}", vote),
+			None => String::new(),
+		};
+
+		println!(
+			"  [{}] {} ({}{}{}{})",
+			client.id, client.name, seat, bot, ping, vote
+		);
+	}
+}
+
+fn print_details(room: &RoomDetails) {
+	println!("Room {}", room.id);
+	println!("State: {:?}", room.state);
+	println!(
+		"Visibility: {}{}",
+		if room.public { "public" } else { "private" },
+		if room.password { ", password" } else { "" }
+	);
+	println!("Seats: {}/{}", room.players.len(), room.num_players);
+	println!("Settings: {}", room.game_setting);
+
+	match &room.vote {
+		Some(vote) => println!("Vote: {:?} ({} votes)", vote, room.num_votes),
+		None => println!("Vote: none"),
+	}
+
+	print_clients("Players", &room.players);
+	print_clients("Spectators", &room.spectators);
+
+	if !room.reservations.is_empty() {
+		println!("Reserved seats:");
+		for (name, plr) in &room.reservations {
+			println!("  {} (seat {})", name, plr);
+		}
+	}
+}
+
+fn print_event(event: &WatchEvent) {
+	let time = chrono::DateTime::from_timestamp_millis(event.timestamp)
+		.map(|t| {
+			t.with_timezone(&chrono::Local)
+				.format("%H:%M:%S%.3f")
+				.to_string()
+		})
+		.unwrap_or_default();
+
+	let peer = match event.peer {
+		Peer::Client(client, Some(plr)) => format!("client {} (player {})", client, plr),
+		Peer::Client(client, None) => format!("client {} (spectator)", client),
+		Peer::Player(plr) => format!("player {}", plr),
+		Peer::Everyone => String::from("everyone"),
+		Peer::EveryoneExceptClient(client) => format!("everyone except client {}", client),
+		Peer::EveryoneExceptPlayer(plr) => format!("everyone except player {}", plr),
+	};
+
+	match &event.direction {
+		Direction::Incoming => println!("[{}] {} -> {}", time, peer, event.message),
+		Direction::Outgoing => println!("[{}] {} <- {}", time, peer, event.message),
+		Direction::Rejected(reason) => {
+			println!("[{}] {} -x {} ({})", time, peer, event.message, reason)
+		}
+	}
+}
+
+/// Print the events of the room as they arrive, until the room is closed
+fn watch(client: &mut AdminClient, room_id: RoomID, json: bool) -> Result<(), Box<dyn Error>> {
+	client.send(&Watch(room_id))?;
+
+	loop {
+		let answer: ServerAnswer = client.receive()?;
+		if json {
+			println!("{}", serde_json::to_string(&answer)?);
+		}
+
+		match answer {
+			Watched(event) if !json => print_event(&event),
+			Watched(_) => {}
+			Error(e) => eprintln!("Error: {}", e),
+			Unsuccessful => {
+				eprintln!("Room not found!");
+				std::process::exit(1);
+			}
+			_ => break,
+		}
+	}
+
+	Ok(())
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
 	let cli = Cli::parse();
 
 	let request = match cli.command {
-		Commands::Close { room_id } => CloseRoom(room_id),
 		Commands::List => ListRooms,
+		Commands::Inspect { room_id } => InspectRoom(room_id),
+		Commands::Close { room_id } => CloseRoom(room_id),
+		Commands::Kick { room_id, client_id } => Kick(room_id, client_id),
+		Commands::Broadcast { notice } => Broadcast(notice),
+		Commands::Maintain => CleanUnused,
+		Commands::Save { path } => SaveToFile(path),
+		Commands::Load { path } => LoadFromFile(path),
+		Commands::Watch { room_id } => Watch(room_id),
 	};
 
-	let packet = bincode::serialize(&request).unwrap();
 	let path = {
 		let mut pathbuf = PathBuf::from(".");
 		pathbuf.push(cli.server);
 		pathbuf.into_os_string()
 	};
 
-	let mut stream = UnixStream::connect(path)?;
-	stream.set_read_timeout(None)?;
-	stream.write_all(&packet)?;
-
-	let mut response = vec![0; 4096];
-	stream.read(&mut response)?;
-
-	let answer: ServerAnswer = bincode::deserialize(&response)?;
-
-	match answer {
-		Successful => println!("Success!"),
-		Unsuccessful => eprintln!("Error happened while doing operation!"),
-		RoomList(rooms) => {
-			let infos: Vec<_> = rooms
-				.iter()
-				.map(|room| {
-					let plrs = room.players.join(", ");
-					format!("{}: {}", room.id, plrs)
-				})
-				.collect();
-
-			let out = infos.join("\n");
-			println!("{}", out);
+	let mut client = AdminClient::connect(path)?;
+	if let Watch(room_id) = request {
+		return watch(&mut client, room_id, cli.json);
+	}
+
+	let answer = client.request(&request)?;
+	let failed = matches!(answer, Unsuccessful | Error(_));
+
+	if cli.json {
+		println!("{}", serde_json::to_string_pretty(&answer)?);
+	} else {
+		match answer {
+			Successful => println!("Success!"),
+			Unsuccessful => eprintln!("Error happened while doing operation!"),
+			Error(e) => eprintln!("Error: {}", e),
+			RoomList(rooms) => {
+				let infos: Vec<_> = rooms
+					.iter()
+					.map(|room| {
+						let plrs = room.players.join(", ");
+						format!("{}: {}", room.id, plrs)
+					})
+					.collect();
+
+				let out = infos.join("\n");
+				println!("{}", out);
+			}
+			Details(room) => print_details(&room),
+			Watched(event) => print_event(&event),
 		}
 	}
 
+	if failed {
+		std::process::exit(1);
+	}
 	Ok(())
 }
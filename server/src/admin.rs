@@ -0,0 +1,296 @@
+//! Framing of the admin socket.
+//!
+//! Every request and answer is sent as one frame: a version byte,
+//! the length of the payload as big-endian u32 and the bincode encoded payload.
+//! A connection may carry any number of requests, each one gets exactly one answer.
+//! Requests the server doesn't know are answered with an error,
+//! so newer tools can talk to older servers.
+
+use serde::{de::DeserializeOwned, Serialize};
+use std::io::{Read, Write};
+use std::os::unix::net;
+use std::path::Path;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::UnixStream;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::room::*;
+
+/// Version of the framing and of the requests, changes whenever old tools would misunderstand new ones
+pub const ADMIN_PROTOCOL_VERSION: u8 = 1;
+
+/// Largest payload accepted in a single frame
+pub const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum FrameError {
+	Io(std::io::Error),
+	/// The other side speaks another version of the protocol
+	Version(u8),
+	TooLarge(usize),
+	Encoding(bincode::Error),
+}
+
+impl std::fmt::Display for FrameError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::Io(e) => write!(f, "{}", e),
+			Self::Version(v) => write!(
+				f,
+				"Protocol version {} is not supported, expected {}",
+				v, ADMIN_PROTOCOL_VERSION
+			),
+			Self::TooLarge(len) => write!(f, "Frame of {} bytes is too large", len),
+			Self::Encoding(e) => write!(f, "Invalid payload: {}", e),
+		}
+	}
+}
+
+impl std::error::Error for FrameError {}
+
+impl From<std::io::Error> for FrameError {
+	fn from(e: std::io::Error) -> Self {
+		Self::Io(e)
+	}
+}
+
+impl From<bincode::Error> for FrameError {
+	fn from(e: bincode::Error) -> Self {
+		Self::Encoding(e)
+	}
+}
+
+/// Encode the message as a complete frame
+pub fn encode_frame<T: Serialize>(msg: &T) -> Result<Vec<u8>, FrameError> {
+	let payload = bincode::serialize(msg)?;
+	if MAX_FRAME_SIZE < payload.len() {
+		return Err(FrameError::TooLarge(payload.len()));
+	}
+
+	let mut frame = Vec::with_capacity(5 + payload.len());
+	frame.push(ADMIN_PROTOCOL_VERSION);
+	frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+	frame.extend_from_slice(&payload);
+	Ok(frame)
+}
+
+/// Check the header of a frame and return the length of its payload
+fn parse_header(header: [u8; 5]) -> Result<usize, FrameError> {
+	let [version, len @ ..] = header;
+	if version != ADMIN_PROTOCOL_VERSION {
+		return Err(FrameError::Version(version));
+	}
+
+	let len = u32::from_be_bytes(len) as usize;
+	if MAX_FRAME_SIZE < len {
+		return Err(FrameError::TooLarge(len));
+	}
+	Ok(len)
+}
+
+/// Read the payload of the next frame, None if the stream ended before it
+pub async fn read_frame<R>(reader: &mut R) -> Result<Option<Vec<u8>>, FrameError>
+where
+	R: AsyncRead + Unpin,
+{
+	let mut header = [0; 5];
+	match reader.read_exact(&mut header).await {
+		Ok(_) => {}
+		Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+		Err(e) => return Err(e.into()),
+	}
+
+	let mut payload = vec![0; parse_header(header)?];
+	reader.read_exact(&mut payload).await?;
+	Ok(Some(payload))
+}
+
+pub async fn write_frame<W, T>(writer: &mut W, msg: &T) -> Result<(), FrameError>
+where
+	W: AsyncWrite + Unpin,
+	T: Serialize,
+{
+	writer.write_all(&encode_frame(msg)?).await?;
+	writer.flush().await?;
+	Ok(())
+}
+
+/// Answer the requests of an admin connection, until it is closed
+pub async fn serve_connection<S, E, G>(mut socket: UnixStream, rooms: &RoomManager<S, E, G>)
+where
+	S: Clone + Send + Serialize + DeserializeOwned,
+	E: Clone + Serialize + DeserializeOwned + Send,
+	G: ServerRoom<E> + TryFrom<S> + Send + Serialize + DeserializeOwned,
+{
+	loop {
+		let answer = match read_frame(&mut socket).await {
+			Ok(Some(payload)) => match bincode::deserialize::<ServerRequest>(&payload) {
+				Ok(ServerRequest::Watch(room_id)) => {
+					watch_room(socket, rooms, &room_id).await;
+					break;
+				}
+				Ok(request) => {
+					debug!("Admin request {:?}", request);
+					rooms.process_request(request).await
+				}
+				Err(_) => ServerAnswer::Error(String::from("Unknown request")),
+			},
+			Ok(None) => break,
+			// The rest of the stream can't be understood, so answer once and give up
+			Err(e) => {
+				warn!("Invalid admin frame: {}", e);
+				let _ = write_frame(&mut socket, &ServerAnswer::Error(e.to_string())).await;
+				break;
+			}
+		};
+
+		if let Err(e) = write_frame(&mut socket, &answer).await {
+			warn!("Could not answer admin request: {}", e);
+			break;
+		}
+	}
+}
+
+/// Stream the messages of the room, until the room or the connection is closed
+async fn watch_room<S, E, G>(mut socket: UnixStream, rooms: &RoomManager<S, E, G>, id: &RoomID)
+where
+	S: Clone + Send,
+	E: Clone + Serialize + DeserializeOwned + Send,
+	G: ServerRoom<E> + TryFrom<S> + Send,
+{
+	let mut events = match rooms.watch_room(id).await {
+		Some(events) => events,
+		None => {
+			let _ = write_frame(&mut socket, &ServerAnswer::Unsuccessful).await;
+			return;
+		}
+	};
+	info!(room = id; "Admin started watching");
+
+	let (mut reader, mut writer) = socket.split();
+	loop {
+		let answer = tokio::select! {
+			event = events.recv() => match event {
+				Ok(event) => ServerAnswer::Watched(event),
+				Err(RecvError::Lagged(n)) => ServerAnswer::Error(format!("Missed {} messages", n)),
+				Err(RecvError::Closed) => {
+					let _ = write_frame(&mut writer, &ServerAnswer::Successful).await;
+					break;
+				}
+			},
+			// The watcher doesn't send anything else, so this means it is gone
+			_ = reader.read_u8() => break,
+		};
+
+		if write_frame(&mut writer, &answer).await.is_err() {
+			break;
+		}
+	}
+	info!(room = id; "Admin stopped watching");
+}
+
+/// Blocking connection to the admin socket of a server
+pub struct AdminClient {
+	stream: net::UnixStream,
+}
+
+impl AdminClient {
+	pub fn connect<P: AsRef<Path>>(path: P) -> Result<Self, FrameError> {
+		let stream = net::UnixStream::connect(path)?;
+		Ok(Self { stream })
+	}
+
+	/// Send the request and wait for its answer
+	pub fn request(&mut self, request: &ServerRequest) -> Result<ServerAnswer, FrameError> {
+		self.send(request)?;
+		self.receive()
+	}
+
+	pub fn send(&mut self, request: &ServerRequest) -> Result<(), FrameError> {
+		self.stream.write_all(&encode_frame(request)?)?;
+		Ok(())
+	}
+
+	/// Wait for the next frame sent by the server
+	pub fn receive<T: DeserializeOwned>(&mut self) -> Result<T, FrameError> {
+		let mut header = [0; 5];
+		self.stream.read_exact(&mut header)?;
+
+		let mut payload = vec![0; parse_header(header)?];
+		self.stream.read_exact(&mut payload)?;
+		Ok(bincode::deserialize(&payload)?)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn request() -> ServerRequest {
+		ServerRequest::Kick(RoomID::from("ABCD"), 3)
+	}
+
+	fn decode(payload: Option<Vec<u8>>) -> ServerRequest {
+		bincode::deserialize(&payload.unwrap()).unwrap()
+	}
+
+	#[tokio::test]
+	async fn frames_arrive_one_by_one() {
+		let mut stream = vec![];
+		write_frame(&mut stream, &request()).await.unwrap();
+		write_frame(&mut stream, &ServerRequest::ListRooms)
+			.await
+			.unwrap();
+
+		let mut reader = stream.as_slice();
+		let first = decode(read_frame(&mut reader).await.unwrap());
+		let second = decode(read_frame(&mut reader).await.unwrap());
+		assert!(matches!(first, ServerRequest::Kick(id, 3) if id == "ABCD"));
+		assert!(matches!(second, ServerRequest::ListRooms));
+		assert!(read_frame(&mut reader).await.unwrap().is_none());
+	}
+
+	#[tokio::test]
+	async fn other_versions_are_refused() {
+		let mut frame = encode_frame(&request()).unwrap();
+		frame[0] = ADMIN_PROTOCOL_VERSION + 1;
+
+		let res = read_frame(&mut frame.as_slice()).await;
+		assert!(matches!(res, Err(FrameError::Version(v)) if v == ADMIN_PROTOCOL_VERSION + 1));
+	}
+
+	#[tokio::test]
+	async fn large_frames_are_refused_before_reading_them() {
+		let mut header = vec![ADMIN_PROTOCOL_VERSION];
+		header.extend_from_slice(&(MAX_FRAME_SIZE as u32 + 1).to_be_bytes());
+
+		let res = read_frame(&mut header.as_slice()).await;
+		assert!(matches!(res, Err(FrameError::TooLarge(len)) if len == MAX_FRAME_SIZE + 1));
+	}
+
+	#[tokio::test]
+	async fn truncated_frames_are_an_error() {
+		let frame = encode_frame(&request()).unwrap();
+
+		let res = read_frame(&mut &frame[..frame.len() - 1]).await;
+		assert!(matches!(res, Err(FrameError::Io(_))));
+	}
+
+	#[tokio::test]
+	async fn client_and_server_understand_each_other() {
+		let (client, server) = net::UnixStream::pair().unwrap();
+		let mut client = AdminClient { stream: client };
+		server.set_nonblocking(true).unwrap();
+		let mut server = UnixStream::from_std(server).unwrap();
+
+		client.send(&request()).unwrap();
+		let received = decode(read_frame(&mut server).await.unwrap());
+		assert!(matches!(received, ServerRequest::Kick(id, 3) if id == "ABCD"));
+
+		write_frame(&mut server, &ServerAnswer::Successful)
+			.await
+			.unwrap();
+		let answer: ServerAnswer = client.receive().unwrap();
+		assert!(matches!(answer, ServerAnswer::Successful));
+	}
+}
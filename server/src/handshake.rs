@@ -0,0 +1,270 @@
+use axum::extract::ws::Message;
+use futures::{Sink, SinkExt, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use tokio::sync::OwnedMutexGuard;
+use tokio::time::Duration;
+
+use crate::room::*;
+use crate::socket_message::*;
+use crate::RoomHandlerRef;
+
+/// How long a new connection has to introduce itself
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The steps a new connection goes through, before it joins a room
+enum Handshake<S, E, G>
+where
+	S: Clone,
+	E: Clone,
+	G: ServerRoom<E> + TryFrom<S>,
+{
+	/// Waiting for the `Introduction` of the client
+	Introduction,
+	/// Check whether the client was built for the same versions
+	CheckVersion(ClientData),
+	/// The client introduced itself, look up the room it wants to join
+	FindRoom(ClientData),
+	/// Check whether the room lets the client in
+	Admit(ClientData, RoomRef<S, E, G>),
+	/// The client can be registered, the room stays locked until then
+	Done(ClientData, RoomRef<S, E, G>, OwnedMutexGuard<Room<S, E, G>>),
+}
+
+/// Wait for the introduction of the client
+async fn receive_introduction<E, W>(ws: &mut W) -> Result<ClientData, CloseReason>
+where
+	E: for<'de> Deserialize<'de>,
+	W: Stream<Item = Result<Message, axum::Error>> + Unpin,
+{
+	let packet = match tokio::time::timeout(HANDSHAKE_TIMEOUT, ws.next()).await {
+		Ok(Some(Ok(packet))) => packet,
+		Ok(_) => return Err(CloseReason::ProtocolMismatch),
+		Err(_) => return Err(CloseReason::Timeout),
+	};
+
+	let text = match packet {
+		Message::Text(text) => text,
+		_ => return Err(CloseReason::ProtocolMismatch),
+	};
+
+	match serde_json::from_str::<SocketMessage<E>>(text.as_str()) {
+		Ok(SocketMessage::Introduction(client)) => Ok(client),
+		_ => Err(CloseReason::ProtocolMismatch),
+	}
+}
+
+/// Run the handshake of a new connection to the room with the given id.
+/// On success, the room is returned locked, so nobody can take the seat in the meantime.
+/// `ws` is usually a WebSocket.
+pub async fn handshake<S, E, G, W>(
+	ws: &mut W,
+	id: &RoomID,
+	addr: SocketAddr,
+	rooms: &RoomHandlerRef<S, E, G>,
+) -> Result<(ClientData, RoomRef<S, E, G>, OwnedMutexGuard<Room<S, E, G>>), CloseReason>
+where
+	S: Clone + Send,
+	E: Clone + Serialize + Send + for<'de> Deserialize<'de>,
+	G: ServerRoom<E> + TryFrom<S> + Send,
+	W: Stream<Item = Result<Message, axum::Error>> + Sink<Message, Error = axum::Error> + Unpin,
+{
+	let mut state = Handshake::Introduction;
+
+	loop {
+		state = match state {
+			Handshake::Introduction => {
+				let mut client = receive_introduction::<E, W>(ws).await?;
+				client.address = Some(addr.ip());
+				Handshake::CheckVersion(client)
+			}
+			Handshake::CheckVersion(client) => {
+				let version = Version {
+					protocol: PROTOCOL_VERSION,
+					game: G::GAME_VERSION,
+				};
+				if client.version != Some(version) {
+					let msg = SocketMessage::<()>::UpgradeRequired(version);
+					let jsonstr = serde_json::to_string(&msg).unwrap();
+					// The socket is closed right after, so a failure doesn't matter
+					let _ = ws.send(Message::Text(jsonstr.into())).await;
+					return Err(CloseReason::UpgradeRequired);
+				}
+				Handshake::FindRoom(client)
+			}
+			Handshake::FindRoom(client) => {
+				let room = rooms.get_room(id);
+				match room {
+					Some(room) => Handshake::Admit(client, room),
+					None => return Err(CloseReason::RoomNotFound),
+				}
+			}
+			Handshake::Admit(client, room) => {
+				let mut lock = room.clone().lock_owned().await;
+				lock.admit(&client).await?;
+				Handshake::Done(client, room, lock)
+			}
+			Handshake::Done(client, room, lock) => return Ok((client, room, lock)),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::room::password::PasswordHash;
+	use crate::room::tests::{TestEvent, TestGame, TestSetting};
+	use std::collections::VecDeque;
+	use std::pin::Pin;
+	use std::sync::Arc;
+	use std::task::{Context, Poll};
+
+	type TestManager = RoomManager<TestSetting, TestEvent, TestGame>;
+
+	/// Receives the given messages and keeps everything sent to it
+	#[derive(Default)]
+	struct TestSocket {
+		incoming: VecDeque<Message>,
+		sent: Vec<Message>,
+	}
+
+	impl Stream for TestSocket {
+		type Item = Result<Message, axum::Error>;
+
+		fn poll_next(mut self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Option<Self::Item>> {
+			Poll::Ready(self.incoming.pop_front().map(Ok))
+		}
+	}
+
+	impl Sink<Message> for TestSocket {
+		type Error = axum::Error;
+
+		fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+			Poll::Ready(Ok(()))
+		}
+
+		fn start_send(mut self: Pin<&mut Self>, msg: Message) -> Result<(), Self::Error> {
+			self.sent.push(msg);
+			Ok(())
+		}
+
+		fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+			Poll::Ready(Ok(()))
+		}
+
+		fn poll_close(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+			Poll::Ready(Ok(()))
+		}
+	}
+
+	fn socket(messages: &[serde_json::Value]) -> TestSocket {
+		TestSocket {
+			incoming: messages
+				.iter()
+				.map(|msg| Message::Text(msg.to_string().into()))
+				.collect(),
+			sent: vec![],
+		}
+	}
+
+	fn introduction(game_version: u32, secret: Option<&str>) -> serde_json::Value {
+		serde_json::json!({
+			"Introduction": {
+				"name": "Alice",
+				"secret": secret,
+				"version": { "protocol": PROTOCOL_VERSION, "game": game_version },
+			}
+		})
+	}
+
+	fn new_room(rooms: &TestManager, password: Option<&str>) -> RoomID {
+		let setting = RoomSetting {
+			public: true,
+			password: password.map(PasswordHash::new),
+			game_setting: TestSetting {
+				min_players: 4,
+				max_players: 4,
+			},
+		};
+		rooms.create_room(setting).unwrap().0
+	}
+
+	fn addr() -> SocketAddr {
+		"127.0.0.1:4000".parse().unwrap()
+	}
+
+	#[tokio::test]
+	async fn admits_into_the_locked_room() {
+		let rooms = Arc::new(TestManager::new());
+		let id = new_room(&rooms, None);
+
+		let mut ws = socket(&[introduction(1, None)]);
+		let (client, room, _lock) = handshake(&mut ws, &id, addr(), &rooms).await.unwrap();
+		assert_eq!(client.name, "Alice");
+		assert_eq!(client.address, Some(addr().ip()));
+		assert!(room.try_lock().is_err());
+	}
+
+	#[tokio::test]
+	async fn expects_an_introduction_first() {
+		let rooms = Arc::new(TestManager::new());
+		let id = new_room(&rooms, None);
+
+		let mut ws = socket(&[serde_json::json!("Pong"), introduction(1, None)]);
+		let res = handshake(&mut ws, &id, addr(), &rooms).await;
+		assert_eq!(res.err(), Some(CloseReason::ProtocolMismatch));
+
+		let mut ws = TestSocket::default();
+		ws.incoming.push_back(Message::Binary(vec![1, 2, 3].into()));
+		let res = handshake(&mut ws, &id, addr(), &rooms).await;
+		assert_eq!(res.err(), Some(CloseReason::ProtocolMismatch));
+
+		// The client went away before introducing itself
+		let res = handshake(&mut TestSocket::default(), &id, addr(), &rooms).await;
+		assert_eq!(res.err(), Some(CloseReason::ProtocolMismatch));
+	}
+
+	#[tokio::test]
+	async fn tells_outdated_clients_the_version() {
+		let rooms = Arc::new(TestManager::new());
+		let id = new_room(&rooms, None);
+
+		let mut ws = socket(&[introduction(0, None)]);
+		let res = handshake(&mut ws, &id, addr(), &rooms).await;
+		assert_eq!(res.err(), Some(CloseReason::UpgradeRequired));
+
+		let Some(Message::Text(text)) = ws.sent.first() else {
+			panic!("No version was sent");
+		};
+		let msg: SocketMessage<TestEvent> = serde_json::from_str(text.as_str()).unwrap();
+		assert!(matches!(
+			msg,
+			SocketMessage::UpgradeRequired(Version {
+				protocol: PROTOCOL_VERSION,
+				game: 1,
+			})
+		));
+	}
+
+	#[tokio::test]
+	async fn refuses_unknown_rooms() {
+		let rooms = Arc::new(TestManager::new());
+
+		let mut ws = socket(&[introduction(1, None)]);
+		let res = handshake(&mut ws, &RoomID::from("NONE"), addr(), &rooms).await;
+		assert_eq!(res.err(), Some(CloseReason::RoomNotFound));
+	}
+
+	#[tokio::test]
+	async fn asks_the_room_for_admission() {
+		let rooms = Arc::new(TestManager::new());
+		let id = new_room(&rooms, Some("secret"));
+
+		let mut ws = socket(&[introduction(1, Some("wrong"))]);
+		let res = handshake(&mut ws, &id, addr(), &rooms).await;
+		assert_eq!(res.err(), Some(CloseReason::WrongPassword));
+
+		let mut ws = socket(&[introduction(1, Some("secret"))]);
+		assert!(handshake(&mut ws, &id, addr(), &rooms).await.is_ok());
+	}
+}
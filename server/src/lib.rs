@@ -1,14 +1,15 @@
 use serde::*;
-use socket_message::{ClientData, SocketMessage};
+use socket_message::{ClientData, CloseReason, SocketMessage};
 use std::marker::Send;
+use std::net::SocketAddr;
 use std::{future::Future, pin::Pin, sync::Arc};
-use tokio::sync::Mutex;
+use tokio::sync::{broadcast::error::RecvError, OwnedMutexGuard};
 
 use tokio::net::UnixListener;
 
 use axum::{
 	extract::ws::{Message, WebSocket, WebSocketUpgrade},
-	extract::Path,
+	extract::{ConnectInfo, Path, Query},
 	http::{Response, StatusCode},
 	routing::{get, post},
 	Router,
@@ -16,112 +17,194 @@ use axum::{
 
 use tower_http::cors;
 
-use futures::StreamExt;
+use futures::{SinkExt, StreamExt};
 
 #[macro_use]
-mod log;
+pub mod log;
 
+pub mod admin;
+mod handshake;
+pub mod limits;
 pub mod room;
 pub mod socket_message;
+pub mod transport;
 
-use room::*;
+use limits::*;
+use room::{
+	client::{close_message, Heartbeat},
+	*,
+};
+
+type RoomHandlerRef<S, E, G> = Arc<RoomManager<S, E, G>>;
 
-type RoomHandlerRef<S, E, G> = Arc<Mutex<RoomManager<S, E, G>>>;
+/// Body of `POST /rooms`, the settings of the game and who may join
+#[derive(Deserialize)]
+struct RoomRequest<S> {
+	setting: S,
+	#[serde(default)]
+	private: bool,
+	#[serde(default)]
+	password: Option<String>,
+}
 
 async fn handle_ws_connection<S, E, G>(
 	mut ws: WebSocket,
 	id: String,
+	addr: SocketAddr,
 	rooms: RoomHandlerRef<S, E, G>,
+	limits: Limits,
 ) where
 	S: Default + Clone + Send + 'static,
 	E: Clone + Send + Serialize + for<'de> Deserialize<'de>,
 	G: ServerRoom<E> + Send + TryFrom<S>,
 {
-	let client = {
-		let timeout = tokio::time::Duration::from_secs(2);
+	let (client, room, lock) = match handshake::handshake(&mut ws, &id, addr, &rooms).await {
+		Ok(res) => res,
+		Err(reason) => {
+			info!(room = id; "Handshake failed: {}", reason.reason());
+			if let Err(e) = ws.send(close_message(reason)).await {
+				error!(room = id; "Could not close socket: {}", e);
+			}
+			return;
+		}
+	};
 
-		let res = tokio::time::timeout(timeout, ws.recv()).await;
+	handle_room(ws, client, room, lock, limits).await;
 
-		let packet = res
-			.expect("Connection timeout!")
-			.expect("Connection closed!")
-			.expect("Error on packet!");
+	rooms.maintain_room(&id).await;
+}
 
-		match packet {
-			Message::Text(text) => {
-				let msg: SocketMessage<E> =
-					serde_json::from_str(text.as_str()).expect("Error on handshake!");
+/// Push the changes of the public rooms to a client of the lobby, until it disconnects
+async fn handle_lobby_connection<S, E, G>(ws: WebSocket, rooms: RoomHandlerRef<S, E, G>)
+where
+	S: Clone + Send,
+	E: Clone + Serialize + for<'de> Deserialize<'de> + Send,
+	G: ServerRoom<E> + TryFrom<S> + Send,
+{
+	let (mut ws_tx, mut ws_rx) = ws.split();
 
-				match msg {
-					SocketMessage::Introduction(c) => c,
-					_ => return,
-				}
-			}
-			_ => return,
-		}
+	// Later changes reach the client anyway, so only the first page of joinable rooms is sent
+	let filter = RoomFilter {
+		joinable: true,
+		limit: Some(MAX_INDEX_PAGE),
+		..Default::default()
 	};
+	let listing = || lobby::LobbyEvent::Rooms(rooms.index_rooms(&filter));
 
-	let room = {
-		let handler = rooms.lock().await;
-		match handler.get_room(&id) {
-			Some(c) => c,
-			None => {
-				error!("Room {} is not available!", id);
-				return;
-			}
-		}
-	};
+	// Subscribe before listing the rooms, so no change in between is lost
+	let mut events = rooms.subscribe_lobby();
+	let mut event = listing();
 
-	handle_room(ws, client, room).await;
+	loop {
+		let jsonstr = serde_json::to_string(&event).unwrap();
+		if ws_tx.send(Message::Text(jsonstr.into())).await.is_err() {
+			break;
+		}
 
-	let mut handler = rooms.lock().await;
-	handler.maintain_room(&id).await;
+		event = loop {
+			tokio::select! {
+				event = events.recv() => match event {
+					Ok(event) => break event,
+					// Start over with the current rooms
+					Err(RecvError::Lagged(_)) => break listing(),
+					Err(RecvError::Closed) => return,
+				},
+				// The lobby only listens, anything but a close frame is ignored
+				msg = ws_rx.next() => match msg {
+					Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+					Some(Ok(_)) => {}
+				},
+			}
+		};
+	}
 }
 
 async fn handle_room<Setting, Event, Game>(
 	ws: WebSocket,
 	client: ClientData,
 	room: RoomRef<Setting, Event, Game>,
+	mut lock: OwnedMutexGuard<Room<Setting, Event, Game>>,
+	limits: Limits,
 ) where
 	Setting: Default + Clone + Send + 'static,
 	Game: ServerRoom<Event> + Send + TryFrom<Setting>,
 	Event: Clone + Serialize + for<'de> Deserialize<'de>,
 {
 	let (ws_tx, mut ws_rx) = ws.split();
-	let (conn, client_id) = room
-		.lock()
-		.await
-		.register(client, ws_tx)
-		.await
-		.expect("The room was already full!");
+	let (conn, client_id) = lock.register(client, Box::new(ws_tx)).await;
+	let room_id = lock.id.clone();
+
+	match lock.clients.get(&client_id) {
+		Some(client) => {
+			info!(room = room_id, client = client_id, player = client.player_id; "Client connected")
+		}
+		None => info!(room = room_id, client = client_id; "Spectator connected"),
+	}
+	drop(lock);
 
-	debug!("Client[{}] connected!", client_id);
+	let mut bucket = TokenBucket::new(limits.burst, limits.messages_per_second);
+	let mut violations = Violations::default();
 
 	while let Some(message) = ws_rx.next().await {
 		let message = match message {
 			Ok(msg) => msg,
 			Err(e) => {
-				error!("{}", e);
-				continue;
+				error!(room = room_id, client = client_id; "{}", e);
+				if exceeds_frame_size(e) {
+					room.lock()
+						.await
+						.record_rejected(client_id, "Message too large", "");
+					conn.lock().await.disconnect(CloseReason::LimitExceeded);
+				}
+				break;
 			}
 		};
 
 		match message {
 			Message::Text(string) => {
-				debug!("Client[{}]: {}", client_id, string);
+				debug!(room = room_id, client = client_id; "{}", string);
 				conn.lock().await.last_response = tokio::time::Instant::now();
 
-				let msg = match serde_json::from_str(string.as_str()) {
+				// Every frame counts towards the limits, even if it can't be parsed
+				let msg = if !bucket.try_take() {
+					Err("Too many messages")
+				} else {
+					match serde_json::from_str::<SocketMessage<Event>>(string.as_str()) {
+						Ok(SocketMessage::ChatMessage(text, _))
+							if limits.max_chat_length < text.chars().count() =>
+						{
+							Err("Chat message too long")
+						}
+						Ok(msg) => Ok(msg),
+						Err(_) => Err("Could not parse message"),
+					}
+				};
+
+				let msg = match msg {
 					Ok(msg) => msg,
-					Err(_) => {
-						error!("Could not parse message!");
+					Err(reason) => {
+						warn!(room = room_id, client = client_id; "{}", reason);
+						room.lock()
+							.await
+							.record_rejected(client_id, reason, string.as_str());
+						if violations.add(&limits) {
+							info!(room = room_id, client = client_id; "Disconnect client for breaking the limits");
+							conn.lock().await.disconnect(CloseReason::LimitExceeded);
+							break;
+						}
 						continue;
 					}
 				};
+
+				if let SocketMessage::Pong = msg {
+					conn.lock().await.pong();
+					continue;
+				}
+
 				room.lock().await.handle_input(msg, client_id).await;
 			}
 			Message::Close(_) => {
-				debug!("Client[{}] connection closed!", client_id);
+				debug!(room = room_id, client = client_id; "Connection closed!");
 				break;
 			}
 			_ => {}
@@ -129,7 +212,17 @@ async fn handle_room<Setting, Event, Game>(
 	}
 
 	room.lock().await.unregister(client_id).await;
-	debug!("Client[{}] disconnected!", client_id);
+	info!(room = room_id, client = client_id; "Client disconnected");
+}
+
+/// Whether reading failed, because the client sent a frame larger than `Limits::max_frame_size`
+fn exceeds_frame_size(e: axum::Error) -> bool {
+	use tungstenite::error::{CapacityError, Error};
+
+	match e.into_inner().downcast::<Error>() {
+		Ok(e) => matches!(*e, Error::Capacity(CapacityError::MessageTooLong { .. })),
+		Err(_) => false,
+	}
 }
 
 pub struct Server<'a> {
@@ -137,6 +230,14 @@ pub struct Server<'a> {
 	name: &'a str,
 
 	socket_file: Option<Box<std::path::Path>>,
+	snapshot_dir: Option<Box<std::path::Path>>,
+	room_ids: Option<(&'a str, usize)>,
+
+	log_level: Option<log::Level>,
+	log_json: bool,
+
+	limits: Limits,
+	heartbeat: Heartbeat,
 }
 
 impl<'a> Server<'a> {
@@ -146,43 +247,129 @@ impl<'a> Server<'a> {
 			name,
 
 			socket_file: None,
+			snapshot_dir: None,
+			room_ids: None,
+
+			log_level: None,
+			log_json: false,
+
+			limits: Limits::default(),
+			heartbeat: Heartbeat::default(),
 		}
 	}
 
+	/// Set the rate and size limits of every connection
+	pub fn limits(mut self, limits: Limits) -> Self {
+		self.limits = limits;
+		self
+	}
+
+	/// Set how often clients are pinged and how long they may stay silent
+	pub fn heartbeat(mut self, heartbeat: Heartbeat) -> Self {
+		self.heartbeat = heartbeat;
+		self
+	}
+
+	/// Only log messages up to the given level, `LOG_LEVEL` in the environment takes precedence
+	pub fn log_level(mut self, level: log::Level) -> Self {
+		self.log_level = Some(level);
+		self
+	}
+
+	/// Log every line as a JSON object, `LOG_FORMAT` in the environment takes precedence
+	pub fn log_json(mut self) -> Self {
+		self.log_json = true;
+		self
+	}
+
+	/// Generate room ids of the given length using only the characters of the alphabet
+	pub fn room_ids(mut self, alphabet: &'a str, length: usize) -> Self {
+		self.room_ids = Some((alphabet, length));
+		self
+	}
+
+	/// Save the rooms into the given directory when shutting down
+	/// and restore them from there on the next startup.
+	/// `SNAPSHOT_DIR` in the environment takes precedence.
+	pub fn snapshot_dir(mut self, path: &'static str) -> Self {
+		let path = std::path::Path::new(path);
+		self.snapshot_dir = Some(Box::from(path));
+		self
+	}
+
+	fn snapshot_file(&self) -> Option<String> {
+		let dir = self.snapshot_dir.as_ref()?;
+		let file = dir.join(format!("{}.json", self.name));
+		Some(file.to_string_lossy().into_owned())
+	}
+
 	pub fn unix_socket(mut self, path: &'static str) -> Self {
 		let path = std::path::Path::new(path);
 		self.socket_file = Some(Box::from(path));
 		self
 	}
 
-	pub async fn build<S, E, G>(self)
+	pub async fn build<S, E, G>(mut self)
 	where
-		S: Default + Clone + for<'de> Deserialize<'de> + Send + 'static,
+		S: Default + Clone + Serialize + for<'de> Deserialize<'de> + Send + 'static,
 		E: Clone + Serialize + for<'de> Deserialize<'de> + Send + 'static,
-		G: ServerRoom<E> + Send + 'static + TryFrom<S>,
+		G: ServerRoom<E> + Serialize + for<'de> Deserialize<'de> + Send + 'static + TryFrom<S>,
 	{
+		if let Some(level) = self.log_level {
+			log::set_level(level);
+		}
+		log::set_json(self.log_json);
+		log::init_from_env();
+
+		if let Some(dir) = std::env::var_os("SNAPSHOT_DIR") {
+			self.snapshot_dir = Some(std::path::PathBuf::from(dir).into_boxed_path());
+		}
+
 		let mut rooms = RoomManager::<S, E, G>::new();
+		if let Some((alphabet, length)) = self.room_ids {
+			rooms.set_id_format(alphabet, length);
+		}
+		rooms.set_heartbeat(self.heartbeat);
+
+		let snapshot_file = self.snapshot_file();
+		if let Some(path) = &snapshot_file {
+			if std::path::Path::new(path).exists() {
+				match rooms.load_from_file(path).await {
+					// Remove the snapshot, so it is not restored a second time after a crash
+					Ok(_) => {
+						if let Err(e) = tokio::fs::remove_file(path).await {
+							error!("Could not remove snapshot {}: {}", path, e);
+						}
+					}
+					Err(e) => {
+						error!("Could not restore rooms from {}: {}", path, e);
+					}
+				}
+			}
+		}
 
 		if cfg!(debug_assertions) {
 			rooms.create_room(RoomSetting {
 				public: true,
+				password: None,
 				game_setting: S::default(),
 			});
 		}
 
-		let roomsref = Arc::from(Mutex::from(rooms));
+		let roomsref = Arc::new(rooms);
 
 		let post_binding = roomsref.clone();
 		let rooms_binding = roomsref.clone();
+		let room_binding = roomsref.clone();
 		let ws_binding = roomsref.clone();
+		let lobby_binding = roomsref.clone();
+		let limits = self.limits;
 
 		let mut app = Router::new()
 			.route(
 				"/rooms",
 				post(|req: String| async move {
-					let mut rooms = post_binding.lock().await;
-
-					let setting: S = match serde_json::from_str(req.as_str()) {
+					let request: RoomRequest<S> = match serde_json::from_str(req.as_str()) {
 						Ok(msg) => msg,
 						Err(_) => {
 							return Response::builder()
@@ -191,9 +378,11 @@ impl<'a> Server<'a> {
 								.unwrap()
 						}
 					};
-					let res = rooms.create_room(RoomSetting {
-						game_setting: setting,
-						public: true,
+					let password = request.password.filter(|p| !p.is_empty());
+					let res = post_binding.create_room(RoomSetting {
+						game_setting: request.setting,
+						public: !request.private,
+						password: password.map(|p| room::password::PasswordHash::new(&p)),
 					});
 
 					match res {
@@ -209,11 +398,8 @@ impl<'a> Server<'a> {
 			)
 			.route(
 				"/rooms",
-				get(|_: String| async move {
-					let index = {
-						let handler = rooms_binding.lock().await;
-						handler.index_rooms().await
-					};
+				get(|Query(filter): Query<RoomFilter>| async move {
+					let index = rooms_binding.index_rooms(&filter);
 
 					let body = serde_json::to_string(&index).unwrap();
 
@@ -223,15 +409,47 @@ impl<'a> Server<'a> {
 						.unwrap()
 				}),
 			)
+			.route(
+				"/rooms/{room_id}",
+				get(|Path(room_id): Path<String>| async move {
+					match room_binding.get_room_index(&room_id) {
+						Some(index) => {
+							let body = serde_json::to_string(&index).unwrap();
+							Response::builder()
+								.status(StatusCode::OK)
+								.body(body)
+								.unwrap()
+						}
+						None => Response::builder()
+							.status(StatusCode::NOT_FOUND)
+							.body(String::from("Room not found"))
+							.unwrap(),
+					}
+				}),
+			)
 			.route(
 				"/ws/{room_id}",
 				get(
-					|ws: WebSocketUpgrade, Path(room_id): Path<String>| async move {
-						ws.on_upgrade(|ws: WebSocket| async move {
-							handle_ws_connection(ws, room_id, ws_binding).await;
-						})
+					move |ws: WebSocketUpgrade,
+					      Path(room_id): Path<String>,
+					      ConnectInfo(addr): ConnectInfo<SocketAddr>| async move {
+						ws.max_message_size(limits.max_frame_size).on_upgrade(
+							move |ws: WebSocket| async move {
+								handle_ws_connection(ws, room_id, addr, ws_binding, limits).await;
+							},
+						)
 					},
 				),
+			)
+			.route(
+				"/lobby",
+				get(move |ws: WebSocketUpgrade| async move {
+					ws.max_message_size(limits.max_frame_size).on_upgrade(
+						move |ws: WebSocket| async move {
+							handle_lobby_connection(ws, lobby_binding).await;
+						},
+					)
+				}),
 			);
 
 		// Allow CORS for debugging
@@ -253,20 +471,33 @@ impl<'a> Server<'a> {
 			loop {
 				tokio::time::sleep(duration).await;
 				debug!("Room maintenance...");
-				rooms.lock().await.maintain().await;
+				rooms.maintain().await;
+			}
+		};
+
+		let rooms = roomsref.clone();
+		let ticker = async move {
+			let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(1));
+
+			loop {
+				interval.tick().await;
+				rooms.tick().await;
 			}
 		};
 
 		let server = async move {
 			let listener = tokio::net::TcpListener::bind(self.addr).await.unwrap();
+			// The address of the peer is needed to ban kicked clients
+			let app = app.into_make_service_with_connect_info::<SocketAddr>();
 			axum::serve(listener, app)
 				.await
 				.expect("Could not start server!");
 		};
 
 		let mut tasks: Vec<Pin<Box<dyn Future<Output = ()> + Send>>> =
-			vec![Box::pin(maintenance), Box::pin(server)];
+			vec![Box::pin(maintenance), Box::pin(ticker), Box::pin(server)];
 
+		let socket_file = self.socket_file.clone();
 		if let Some(path) = self.socket_file {
 			let rooms = roomsref.clone();
 			let unix_socket = async move {
@@ -281,18 +512,7 @@ impl<'a> Server<'a> {
 				while let Ok((socket, _addr)) = listener.accept().await {
 					let roomsref = rooms.clone();
 					tokio::spawn(async move {
-						let binding = roomsref.clone();
-						let mut handler = binding.lock().await;
-
-						let mut data = vec![];
-						socket.try_read_buf(&mut data).unwrap();
-
-						let cmd: ServerRequest = bincode::deserialize(&data).unwrap();
-						let answer = handler.process_request(cmd).await;
-
-						let msg = bincode::serialize(&answer).unwrap();
-						// println!("{:?}", msg);
-						socket.try_write(&msg).unwrap();
+						admin::serve_connection(socket, &roomsref).await;
 					});
 				}
 			};
@@ -300,8 +520,112 @@ impl<'a> Server<'a> {
 			tasks.push(Box::pin(unix_socket));
 		}
 
-		println!("Started server {} at {}", self.name, self.addr);
+		info!("Started server {} at {}", self.name, self.addr);
+
+		tokio::select! {
+			_ = futures::future::join_all(tasks) => {}
+			_ = shutdown_signal() => {}
+		}
+
+		info!("Shutting down server {}", self.name);
+
+		roomsref
+			.announce_maintenance("The server is shutting down for maintenance")
+			.await;
+
+		if let Some(path) = &snapshot_file {
+			if let Some(dir) = &self.snapshot_dir {
+				if let Err(e) = tokio::fs::create_dir_all(dir).await {
+					error!("Could not create snapshot directory: {}", e);
+				}
+			}
+			if let Err(e) = roomsref.save_to_file(path).await {
+				error!("Could not save rooms to {}: {}", path, e);
+			}
+		}
+
+		roomsref.close_all().await;
+		// Give the writer tasks a moment to send the notice and close the sockets
+		tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+		if let Some(path) = socket_file {
+			if let Err(e) = std::fs::remove_file(&path) {
+				error!("Could not remove socket: {}", e);
+			}
+		}
+	}
+}
+
+/// Wait until the process is asked to terminate (SIGTERM or Ctrl+C)
+async fn shutdown_signal() {
+	let ctrl_c = async {
+		if let Err(e) = tokio::signal::ctrl_c().await {
+			error!("Could not listen for Ctrl+C: {}", e);
+			std::future::pending::<()>().await;
+		}
+	};
+
+	#[cfg(unix)]
+	let terminate = async {
+		use tokio::signal::unix::{signal, SignalKind};
+		match signal(SignalKind::terminate()) {
+			Ok(mut sig) => {
+				sig.recv().await;
+			}
+			Err(e) => {
+				error!("Could not listen for SIGTERM: {}", e);
+				std::future::pending::<()>().await;
+			}
+		}
+	};
+
+	#[cfg(not(unix))]
+	let terminate = std::future::pending::<()>();
+
+	tokio::select! {
+		_ = ctrl_c => {}
+		_ = terminate => {}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::sync::Mutex;
+	use tokio::sync::oneshot;
+
+	#[tokio::test]
+	async fn oversized_frames_are_recognized() {
+		let (tx, rx) = oneshot::channel();
+		let tx = Arc::new(Mutex::new(Some(tx)));
+
+		// Tells whether the first frame was too large
+		let app = Router::new().route(
+			"/",
+			get(move |ws: WebSocketUpgrade| async move {
+				ws.max_message_size(16)
+					.on_upgrade(move |mut ws| async move {
+						let exceeded = match ws.recv().await {
+							Some(Err(e)) => exceeds_frame_size(e),
+							_ => false,
+						};
+						if let Some(tx) = tx.lock().unwrap().take() {
+							let _ = tx.send(exceeded);
+						}
+					})
+			}),
+		);
+
+		let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let addr = listener.local_addr().unwrap();
+		tokio::spawn(async move { axum::serve(listener, app).await });
+
+		let (mut ws, _) = tokio_tungstenite::connect_async(format!("ws://{}", addr))
+			.await
+			.unwrap();
+		let frame = tungstenite::Message::Text("x".repeat(64).into());
+		ws.send(frame).await.unwrap();
 
-		futures::future::join_all(tasks).await;
+		assert!(rx.await.unwrap());
 	}
 }
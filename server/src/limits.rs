@@ -0,0 +1,155 @@
+use tokio::time::{Duration, Instant};
+
+/// Limits every connection has to respect
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+	/// How many messages a client may send per second on average
+	pub messages_per_second: f64,
+	/// How many messages a client may send at once
+	pub burst: u32,
+	/// Maximum size of a single message in bytes
+	pub max_frame_size: usize,
+	/// Maximum number of characters of a chat message
+	pub max_chat_length: usize,
+	/// After how many violations within `violation_window` a client is disconnected
+	pub max_violations: u32,
+	pub violation_window: Duration,
+}
+
+impl Default for Limits {
+	fn default() -> Self {
+		Self {
+			messages_per_second: 10.0,
+			burst: 30,
+			max_frame_size: 16 * 1024,
+			max_chat_length: 500,
+			max_violations: 5,
+			violation_window: Duration::from_secs(60),
+		}
+	}
+}
+
+/// Token bucket, which refills continuously
+pub struct TokenBucket {
+	capacity: f64,
+	tokens: f64,
+	rate: f64,
+	last: Instant,
+}
+
+impl TokenBucket {
+	pub fn new(capacity: u32, rate: f64) -> Self {
+		Self {
+			capacity: capacity as f64,
+			tokens: capacity as f64,
+			rate,
+			last: Instant::now(),
+		}
+	}
+
+	/// Take a token, returns false if there is none left
+	pub fn try_take(&mut self) -> bool {
+		let now = Instant::now();
+		let elapsed = now.duration_since(self.last).as_secs_f64();
+		self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+		self.last = now;
+
+		if 1.0 <= self.tokens {
+			self.tokens -= 1.0;
+			true
+		} else {
+			false
+		}
+	}
+}
+
+/// Keeps track of how often a connection broke the limits
+pub struct Violations {
+	count: u32,
+	last: Instant,
+}
+
+impl Default for Violations {
+	fn default() -> Self {
+		Self {
+			count: 0,
+			last: Instant::now(),
+		}
+	}
+}
+
+impl Violations {
+	/// Register a violation, returns true if the client should be disconnected
+	pub fn add(&mut self, limits: &Limits) -> bool {
+		if limits.violation_window <= self.last.elapsed() {
+			self.count = 0;
+		}
+		self.count += 1;
+		self.last = Instant::now();
+
+		limits.max_violations <= self.count
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn limits(max_violations: u32, violation_window: Duration) -> Limits {
+		Limits {
+			max_violations,
+			violation_window,
+			..Limits::default()
+		}
+	}
+
+	#[test]
+	fn bucket_allows_a_burst() {
+		let mut bucket = TokenBucket::new(3, 0.0);
+		assert!(bucket.try_take());
+		assert!(bucket.try_take());
+		assert!(bucket.try_take());
+		assert!(!bucket.try_take());
+	}
+
+	#[test]
+	fn bucket_refills_over_time() {
+		let mut bucket = TokenBucket::new(1, 100.0);
+		assert!(bucket.try_take());
+		assert!(!bucket.try_take());
+
+		std::thread::sleep(std::time::Duration::from_millis(20));
+		assert!(bucket.try_take());
+	}
+
+	#[test]
+	fn bucket_never_exceeds_its_capacity() {
+		let mut bucket = TokenBucket::new(2, 1000.0);
+		std::thread::sleep(std::time::Duration::from_millis(20));
+
+		bucket.rate = 0.0;
+		assert!(bucket.try_take());
+		assert!(bucket.try_take());
+		assert!(!bucket.try_take());
+	}
+
+	#[test]
+	fn violations_disconnect_at_the_maximum() {
+		let limits = limits(3, Duration::from_secs(60));
+		let mut violations = Violations::default();
+		assert!(!violations.add(&limits));
+		assert!(!violations.add(&limits));
+		assert!(violations.add(&limits));
+	}
+
+	#[test]
+	fn violations_are_forgotten_after_the_window() {
+		let limits = limits(2, Duration::from_millis(10));
+		let mut violations = Violations::default();
+		assert!(!violations.add(&limits));
+
+		std::thread::sleep(std::time::Duration::from_millis(20));
+		assert!(!violations.add(&limits));
+		assert!(violations.add(&limits));
+	}
+}
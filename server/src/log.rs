@@ -1,27 +1,185 @@
+//! Leveled logging, configurable at runtime.
+//!
+//! Every macro optionally takes context fields before the message:
+//! `debug!(room = id, client = client_id; "Client connected")`
+
+use std::fmt::Arguments;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Level {
+	Error = 0,
+	Warn = 1,
+	Info = 2,
+	Debug = 3,
+}
+
+impl Level {
+	fn name(&self) -> &'static str {
+		match self {
+			Level::Error => "ERROR",
+			Level::Warn => "WARN",
+			Level::Info => "INFO",
+			Level::Debug => "DEBUG",
+		}
+	}
+
+	fn color(&self) -> &'static str {
+		match self {
+			Level::Error => "\x1b[31m",
+			Level::Warn => "\x1b[33m",
+			Level::Info => "\x1b[32m",
+			Level::Debug => "\x1b[36m",
+		}
+	}
+
+	fn from_u8(level: u8) -> Self {
+		match level {
+			0 => Level::Error,
+			1 => Level::Warn,
+			2 => Level::Info,
+			_ => Level::Debug,
+		}
+	}
+}
+
+impl FromStr for Level {
+	type Err = ();
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.to_lowercase().as_str() {
+			"error" => Ok(Level::Error),
+			"warn" => Ok(Level::Warn),
+			"info" => Ok(Level::Info),
+			"debug" => Ok(Level::Debug),
+			_ => Err(()),
+		}
+	}
+}
+
+const DEFAULT_LEVEL: Level = if cfg!(debug_assertions) {
+	Level::Debug
+} else {
+	Level::Info
+};
+
+static LEVEL: AtomicU8 = AtomicU8::new(DEFAULT_LEVEL as u8);
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// Only log messages up to the given level
+pub fn set_level(level: Level) {
+	LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn level() -> Level {
+	Level::from_u8(LEVEL.load(Ordering::Relaxed))
+}
+
+/// Print every log line as a JSON object instead of text
+pub fn set_json(json: bool) {
+	JSON.store(json, Ordering::Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool {
+	level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+/// Configure the logger with `LOG_LEVEL` (error, warn, info, debug)
+/// and `LOG_FORMAT` (text, json) from the environment
+pub fn init_from_env() {
+	if let Ok(level) = std::env::var("LOG_LEVEL") {
+		match level.parse() {
+			Ok(level) => set_level(level),
+			Err(_) => eprintln!("Unknown log level: {}", level),
+		}
+	}
+
+	if let Ok(format) = std::env::var("LOG_FORMAT") {
+		set_json(format.eq_ignore_ascii_case("json"));
+	}
+}
+
+#[doc(hidden)]
+pub fn write(level: Level, file: &str, line: u32, fields: &[(&str, String)], args: Arguments) {
+	let now = chrono::offset::Local::now();
+
+	if JSON.load(Ordering::Relaxed) {
+		let mut obj = serde_json::Map::new();
+		obj.insert("time".into(), now.to_rfc3339().into());
+		obj.insert("level".into(), level.name().into());
+		obj.insert("file".into(), file.into());
+		obj.insert("line".into(), line.into());
+		for (key, value) in fields {
+			obj.insert(key.to_string(), value.clone().into());
+		}
+		obj.insert("message".into(), args.to_string().into());
+
+		println!("{}", serde_json::Value::Object(obj));
+	} else {
+		let context: String = fields
+			.iter()
+			.map(|(key, value)| format!("{}={} ", key, value))
+			.collect();
+
+		println!(
+			"[ {} | {}:{}: {}{}\x1b[0m ] {}{}",
+			now.format("%H:%M:%S"),
+			file,
+			line,
+			level.color(),
+			level.name(),
+			context,
+			args,
+		);
+	}
+}
+
 macro_rules! log {
-    ($t:expr, $($args:tt)*) => {
-        #[cfg(debug_assertions)]
-        {
-            print!(
-                "[ {} | {}:{}: {} ] ",
-                chrono::offset::Local::now().format("%H:%M:%S"),
+    ($level:expr, $($key:ident = $value:expr),+; $($args:tt)+) => {
+        if $crate::log::enabled($level) {
+            $crate::log::write(
+                $level,
+                std::file!(),
+                std::line!(),
+                &[$((std::stringify!($key), $value.to_string())),+],
+                std::format_args!($($args)+),
+            )
+        }
+    };
+    ($level:expr, $($args:tt)+) => {
+        if $crate::log::enabled($level) {
+            $crate::log::write(
+                $level,
                 std::file!(),
                 std::line!(),
-                $t,
-            );
-            println!($($args)*)
+                &[],
+                std::format_args!($($args)+),
+            )
         }
     };
 }
 
 macro_rules! debug {
     ($($args:tt)*) => {
-        log!("\x1b[36mDEBUG\x1b[0m",$($args)*)
+        log!($crate::log::Level::Debug, $($args)*)
+    };
+}
+
+macro_rules! info {
+    ($($args:tt)*) => {
+        log!($crate::log::Level::Info, $($args)*)
+    };
+}
+
+macro_rules! warn {
+    ($($args:tt)*) => {
+        log!($crate::log::Level::Warn, $($args)*)
     };
 }
 
 macro_rules! error {
     ($($args:tt)*) => {
-        log!("\x1b[31mERROR\x1b[0m",$($args)*)
+        log!($crate::log::Level::Error, $($args)*)
     };
 }
@@ -0,0 +1,9 @@
+/// Strategy of a bot player, provided by the game.
+///
+/// Bots are clients without a socket. They are asked about once a second
+/// whether they want to act and decide by looking at the game of the room.
+/// A bot must only use what its player is allowed to see.
+pub trait Bot<G: ?Sized, T>: Send {
+	/// The event the bot wants to play now, if any
+	fn act(&mut self, game: &G, plr_id: usize) -> Option<T>;
+}
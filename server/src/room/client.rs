@@ -1,55 +1,206 @@
-use axum::extract::ws::{Message, WebSocket};
-use futures::stream::SplitSink;
-use futures::SinkExt;
+use axum::extract::ws::{CloseFrame, Message};
 use serde::Serialize;
 
 use std::collections::HashMap;
 use std::ops::{Deref, DerefMut};
-use tokio::time::Instant;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::time::{Duration, Instant};
 
+use super::watch::{Direction, Peer, Watch};
 use crate::socket_message::*;
+use crate::transport::Transport;
 
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
-pub type WsWriter = SplitSink<WebSocket, Message>;
 pub type ConnectionRef = Arc<Mutex<Connection>>;
 
+/// How many messages may wait for a client, before it is dropped for not keeping up
+pub const OUTBOX_CAPACITY: usize = 256;
+/// How long writing a single message may take
+pub const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often clients are pinged and after how long without an answer they count as gone
+#[derive(Clone, Copy, Debug)]
+pub struct Heartbeat {
+	pub interval: Duration,
+	pub timeout: Duration,
+}
+
+impl Default for Heartbeat {
+	fn default() -> Self {
+		Self {
+			interval: Duration::from_secs(5),
+			timeout: Duration::from_secs(20),
+		}
+	}
+}
+
 pub struct Connection {
-	pub ws: WsWriter,
+	/// Messages waiting for the writer task, None once the connection is closed
+	outbox: Option<mpsc::Sender<Message>>,
+	/// Set if the client couldn't keep up, the writer then skips the queued messages
+	dropped: Arc<AtomicBool>,
+
 	pub last_response: Instant,
+	/// When the last ping was sent
+	pub ping_sent: Option<Instant>,
+	/// Round-trip time of the last answered ping
+	pub latency: Option<Duration>,
 }
 
 impl Connection {
-	pub fn new(ws_tx: WsWriter) -> Self {
+	/// Spawn the task writing the queued messages to the transport
+	pub fn new(transport: Box<dyn Transport>) -> Self {
+		let (tx, rx) = mpsc::channel(OUTBOX_CAPACITY);
+		let dropped = Arc::new(AtomicBool::new(false));
+		tokio::spawn(write_outbox(transport, rx, dropped.clone()));
+
 		Self {
-			ws: ws_tx,
+			outbox: Some(tx),
+			dropped,
 			last_response: Instant::now(),
+			ping_sent: None,
+			latency: None,
+		}
+	}
+
+	/// Queue a message without waiting for the client.
+	/// Returns false, if the queue overflowed and the client was dropped.
+	pub fn send(&mut self, msg: Message) -> bool {
+		let outbox = match &self.outbox {
+			Some(outbox) => outbox,
+			None => return true,
+		};
+
+		match outbox.try_send(msg) {
+			Ok(_) => true,
+			Err(TrySendError::Full(_)) => {
+				self.abort();
+				false
+			}
+			// The writer stopped, because the transport failed
+			Err(TrySendError::Closed(_)) => {
+				self.outbox = None;
+				true
+			}
+		}
+	}
+
+	pub fn ping(&mut self) {
+		let jsonstr = serde_json::to_string(&SocketMessage::<()>::Ping).unwrap();
+		self.ping_sent = Some(Instant::now());
+		self.send(Message::Text(jsonstr.into()));
+	}
+
+	/// The client answered the last ping, answers without a ping are ignored
+	pub fn pong(&mut self) {
+		if let Some(sent) = self.ping_sent.take() {
+			self.last_response = Instant::now();
+			self.latency = Some(sent.elapsed());
+		}
+	}
+
+	/// Whether the connection was dropped, because the client couldn't keep up
+	pub fn is_dropped(&self) -> bool {
+		self.dropped.load(Ordering::Relaxed)
+	}
+
+	/// Drop the connection without writing the queued messages
+	pub fn abort(&mut self) {
+		self.dropped.store(true, Ordering::Relaxed);
+		self.outbox = None;
+	}
+
+	/// Close the connection, after the queued messages are written
+	pub fn close(&mut self) {
+		self.outbox = None;
+	}
+
+	/// Close the socket, telling the client why
+	pub fn disconnect(&mut self, reason: CloseReason) {
+		if self.send(close_message(reason)) {
+			self.close();
 		}
 	}
 }
 
+/// Write the queued messages to the transport, until the queue is closed or the client dropped
+async fn write_outbox(
+	mut transport: Box<dyn Transport>,
+	mut outbox: mpsc::Receiver<Message>,
+	dropped: Arc<AtomicBool>,
+) {
+	while let Some(msg) = outbox.recv().await {
+		if dropped.load(Ordering::Relaxed) {
+			break;
+		}
+
+		match tokio::time::timeout(WRITE_TIMEOUT, transport.send(msg)).await {
+			Ok(Ok(_)) => {}
+			Ok(Err(e)) => {
+				debug!("Could not write to client: {}", e);
+				dropped.store(true, Ordering::Relaxed);
+				break;
+			}
+			Err(_) => {
+				debug!("Writing to client timed out");
+				dropped.store(true, Ordering::Relaxed);
+				break;
+			}
+		}
+	}
+
+	if dropped.load(Ordering::Relaxed) {
+		let msg = close_message(CloseReason::LimitExceeded);
+		let _ = tokio::time::timeout(WRITE_TIMEOUT, transport.send(msg)).await;
+	}
+	if let Ok(Err(e)) = tokio::time::timeout(WRITE_TIMEOUT, transport.close()).await {
+		debug!("Could not close socket: {}", e);
+	}
+}
+
+/// Close frame with the code and reason of the given CloseReason
+pub fn close_message(reason: CloseReason) -> Message {
+	Message::Close(Some(CloseFrame {
+		code: reason.code(),
+		reason: reason.reason().into(),
+	}))
+}
+
 pub struct Client {
 	pub data: ClientData,
 	pub player_id: usize,
+	pub session: SessionToken,
 	pub vote: Option<usize>,
 	pub connection: ConnectionRef,
 }
 
 impl Client {
-	pub fn new(data: ClientData, player_id: usize, ws_tx: WsWriter) -> Self {
+	pub fn new(
+		data: ClientData,
+		player_id: usize,
+		session: SessionToken,
+		connection: Connection,
+	) -> Self {
 		Client {
 			data,
 			player_id,
+			session,
 			vote: None,
-			connection: Arc::from(Mutex::from(Connection::new(ws_tx))),
+			connection: Arc::from(Mutex::from(connection)),
 		}
 	}
 
+	pub fn is_bot(&self) -> bool {
+		self.data.bot
+	}
+
 	pub async fn send_msg(&mut self, data: Message) {
 		let mut conn = self.connection.lock().await;
-		if let Err(e) = conn.ws.send(data).await {
-			error!("Error sending data socket: {}", e)
+		if !conn.send(data) {
+			warn!(player = self.player_id; "Outbound queue overflowed, drop client");
 		}
 	}
 
@@ -59,25 +210,8 @@ impl Client {
 		self.send_msg(msg).await;
 	}
 
-	pub async fn is_active(&mut self) -> bool {
-		self.send(SocketMessage::<()>::Ping).await;
-
-		let wait = tokio::time::Duration::from_secs(1);
-		let duration = tokio::time::Duration::from_secs(2);
-		tokio::time::sleep(wait).await;
-
-		let last = self.connection.lock().await.last_response;
-		last.elapsed() < duration
-	}
-
 	pub async fn close(&mut self) {
-		let mut conn = self.connection.lock().await;
-		match conn.ws.close().await {
-			Ok(_) => {}
-			Err(e) => {
-				error!("Could not close socket: {}", e)
-			}
-		}
+		self.connection.lock().await.close();
 	}
 }
 
@@ -85,6 +219,13 @@ impl Client {
 pub struct ClientHandler {
 	client_next: usize,
 	pub clients: HashMap<usize, Client>,
+
+	/// Clients only watching the game.
+	/// They receive public messages only and have no valid player_id.
+	pub spectators: HashMap<usize, Client>,
+
+	/// Every message sent to the clients, for the admin
+	pub watch: Watch,
 }
 
 impl ClientHandler {
@@ -92,25 +233,63 @@ impl ClientHandler {
 		&mut self,
 		client: ClientData,
 		plr_id: usize,
-		ws_tx: WsWriter,
+		session: SessionToken,
+		transport: Box<dyn Transport>,
 	) -> (ConnectionRef, usize) {
 		let id = self.client_next;
 		self.client_next += 1;
 
-		let client = Client::new(client, plr_id, ws_tx);
+		let client = Client::new(client, plr_id, session, Connection::new(transport));
 		let conn = client.connection.clone();
 		self.clients.insert(id, client);
 		(conn, id)
 	}
 
+	pub fn register_spectator(
+		&mut self,
+		client: ClientData,
+		session: SessionToken,
+		transport: Box<dyn Transport>,
+	) -> (ConnectionRef, usize) {
+		let id = self.client_next;
+		self.client_next += 1;
+
+		let client = Client::new(client, usize::MAX, session, Connection::new(transport));
+		let conn = client.connection.clone();
+		self.spectators.insert(id, client);
+		(conn, id)
+	}
+
+	pub fn is_spectator(&self, client_id: usize) -> bool {
+		self.spectators.contains_key(&client_id)
+	}
+
+	/// Iterate over players and spectators
+	fn everyone(&mut self) -> impl Iterator<Item = (&usize, &mut Client)> {
+		self.clients.iter_mut().chain(self.spectators.iter_mut())
+	}
+
 	// General Sending
 
 	pub async fn send_to<T>(&mut self, client_id: usize, data: T)
 	where
 		T: Serialize + Clone,
 	{
-		if let Some(client) = self.clients.get_mut(&client_id) {
-			client.send(data).await;
+		let (client, plr_id) = match self.clients.get_mut(&client_id) {
+			Some(client) => {
+				let plr_id = client.player_id;
+				(Some(client), Some(plr_id))
+			}
+			None => (self.spectators.get_mut(&client_id), None),
+		};
+
+		if let Some(client) = client {
+			let jsonstr = serde_json::to_string(&data).unwrap();
+			let peer = Peer::Client(client_id, plr_id);
+			self.watch
+				.record(Direction::Outgoing, peer, || jsonstr.clone());
+
+			client.send_msg(Message::Text(jsonstr.into())).await;
 		}
 	}
 
@@ -119,11 +298,13 @@ impl ClientHandler {
 		T: Serialize + Clone,
 	{
 		let jsonstr = serde_json::to_string(&data).unwrap();
+		let peer = Peer::EveryoneExceptClient(client_id);
+		self.watch
+			.record(Direction::Outgoing, peer, || jsonstr.clone());
 		let msg = Message::Text(jsonstr.into());
 
 		let futures = self
-			.clients
-			.iter_mut()
+			.everyone()
 			.filter(|(&id, _)| id != client_id)
 			.map(|(_, client)| client.send_msg(msg.clone()));
 
@@ -135,11 +316,12 @@ impl ClientHandler {
 		T: Serialize + Clone,
 	{
 		let jsonstr = serde_json::to_string(&data).unwrap();
+		self.watch
+			.record(Direction::Outgoing, Peer::Everyone, || jsonstr.clone());
 		let msg = Message::Text(jsonstr.into());
 
 		let futures = self
-			.clients
-			.iter_mut()
+			.everyone()
 			.map(|(_, client)| client.send_msg(msg.clone()));
 
 		futures::future::join_all(futures).await;
@@ -147,7 +329,7 @@ impl ClientHandler {
 
 	// Event Sending
 
-	/// Send an event to everyone
+	/// Send an event to everyone, including spectators
 	pub async fn ev_send_to_all<T>(&mut self, data: T)
 	where
 		T: Serialize + Clone,
@@ -155,6 +337,15 @@ impl ClientHandler {
 		self.send_to_all(SocketMessage::<T>::Event(data)).await;
 	}
 
+	/// Send an event to the client (or spectator) with the given client_id
+	pub async fn ev_send_to_client<T>(&mut self, client_id: usize, data: T)
+	where
+		T: Serialize + Clone,
+	{
+		self.send_to(client_id, SocketMessage::<T>::Event(data))
+			.await;
+	}
+
 	/// Send an event to only one player (not client)
 	pub async fn ev_send_to<T>(&mut self, plr_id: usize, data: T)
 	where
@@ -163,24 +354,32 @@ impl ClientHandler {
 		let ev = SocketMessage::<T>::Event(data);
 		for (_, client) in self.clients.iter_mut() {
 			if client.player_id == plr_id {
-				client.send(ev).await;
+				let jsonstr = serde_json::to_string(&ev).unwrap();
+				self.watch
+					.record(Direction::Outgoing, Peer::Player(plr_id), || {
+						jsonstr.clone()
+					});
+
+				client.send_msg(Message::Text(jsonstr.into())).await;
 				break;
 			}
 		}
 	}
 
-	/// Send an event to all players, except the one with the given plr_id
+	/// Send an event to everyone, except the player with the given plr_id
 	pub async fn ev_send_to_all_except<T>(&mut self, plr_id: usize, data: T)
 	where
 		T: Serialize + Clone,
 	{
 		let ev = SocketMessage::<T>::Event(data);
 		let jsonstr = serde_json::to_string(&ev).unwrap();
+		let peer = Peer::EveryoneExceptPlayer(plr_id);
+		self.watch
+			.record(Direction::Outgoing, peer, || jsonstr.clone());
 		let msg = Message::Text(jsonstr.into());
 
 		let futures = self
-			.clients
-			.iter_mut()
+			.everyone()
 			.filter(|(_, client)| client.player_id != plr_id)
 			.map(|(_, client)| client.send_msg(msg.clone()));
 
@@ -201,3 +400,37 @@ impl DerefMut for ClientHandler {
 		&mut self.clients
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::transport::{ChannelTransport, NullTransport};
+
+	#[tokio::test]
+	async fn only_answers_to_pings_count() {
+		let mut conn = Connection::new(Box::new(NullTransport));
+		conn.pong();
+		assert_eq!(conn.latency, None);
+
+		conn.ping();
+		conn.pong();
+		let latency = conn.latency;
+		assert!(latency.is_some());
+
+		conn.pong();
+		assert_eq!(conn.latency, latency);
+		assert_eq!(conn.ping_sent, None);
+	}
+
+	/// The browser client matches these exact strings
+	#[tokio::test]
+	async fn pings_are_bare_strings() {
+		let (transport, mut rx) = ChannelTransport::new();
+		let mut conn = Connection::new(Box::new(transport));
+		conn.ping();
+		assert_eq!(rx.recv().await, Some(Message::Text("\"Ping\"".into())));
+
+		let pong: SocketMessage<()> = serde_json::from_str("\"Pong\"").unwrap();
+		assert!(matches!(pong, SocketMessage::Pong));
+	}
+}
@@ -0,0 +1,18 @@
+use serde::Serialize;
+
+use super::{RoomID, RoomIndex};
+
+/// How many lobby events a client may fall behind, before it is sent all rooms again
+pub const LOBBY_CAPACITY: usize = 256;
+
+/// Changes of the public rooms, pushed to the clients of the lobby
+#[derive(Serialize, Clone)]
+pub enum LobbyEvent {
+	/// The oldest joinable public rooms, sent when connecting and after falling behind.
+	/// At most `MAX_INDEX_PAGE`, the others are only sent once they change.
+	Rooms(Vec<RoomIndex>),
+	RoomCreated(RoomIndex),
+	/// Players joined or left, or the state of the room changed
+	RoomUpdated(RoomIndex),
+	RoomClosed(RoomID),
+}
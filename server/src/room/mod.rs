@@ -1,24 +1,55 @@
+pub mod bot;
 pub mod client;
-
-use rand::prelude::SliceRandom;
-use std::{ops::Deref, sync::Arc};
-use tokio::sync::Mutex;
+pub mod lobby;
+pub mod password;
+pub mod registry;
+pub mod snapshot;
+pub mod teaming;
+pub mod watch;
+
+#[cfg(test)]
+pub(crate) mod tests;
+
+use rand::{distributions::Alphanumeric, seq::SliceRandom, Rng};
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+use tokio::sync::{broadcast, Mutex};
+use tokio::time::{Duration, Instant};
 
 use async_trait::async_trait;
 use std::{collections::HashMap, marker::PhantomData};
 
 use crate::socket_message::{SocketMessage::*, *};
+use crate::transport::{NullTransport, Transport};
+use bot::Bot;
 use client::*;
-use serde::*;
+use lobby::{LobbyEvent, LOBBY_CAPACITY};
+use password::PasswordHash;
+use registry::Registry;
+use serde::{de::DeserializeOwned, *};
+use snapshot::RoomSnapshot;
+use watch::{Direction, Peer, WatchEvent};
 
 #[async_trait]
 pub trait ServerRoom<T> {
-	type Err;
+	/// Version of the events `T`, clients have to be built with the same version
+	const GAME_VERSION: u32;
+
+	/// Sent to the client, whose event was rejected
+	type Err: Serialize + Clone + Send;
 
-	async fn start(&mut self, clients: &mut ClientHandler) -> Result<(), Self::Err>;
+	/// Start a new game with the given number of players.
+	/// The players have the ids 0 to num_players-1.
+	async fn start(
+		&mut self,
+		clients: &mut ClientHandler,
+		num_players: usize,
+	) -> Result<(), Self::Err>;
 
 	async fn on_enter(&mut self, clients: &mut ClientHandler, plr_id: usize);
 	async fn on_leave(&mut self, clients: &mut ClientHandler, plr_id: usize);
+	/// Called when a spectator joins, the game should send it a public view of the game
+	async fn on_spectate(&mut self, clients: &mut ClientHandler, client_id: usize);
 	async fn on_event(
 		&mut self,
 		clients: &mut ClientHandler,
@@ -26,23 +57,124 @@ pub trait ServerRoom<T> {
 		plr_id: usize,
 	) -> Result<(), Self::Err>;
 
+	/// The player who has to act now, if the game limits the time of a turn.
+	/// The room gives them `TURN_TIME` to act.
+	fn on_turn(&self) -> Option<usize>;
+	/// Whether the event ends the turn, which starts the next deadline.
+	/// Events like announcements, which are part of a turn, don't.
+	fn ends_turn(&self, event: &T) -> bool;
+	/// Called when the deadline expired, the game should act in place of the player
+	async fn on_timeout(&mut self, clients: &mut ClientHandler, plr_id: usize);
+
+	/// A new bot player, None if the game can't be played by bots
+	fn new_bot(&self) -> Option<Box<dyn Bot<Self, T>>>;
+
+	/// Short description of the settings for the room index, like the goal of the game
+	fn summary(&self) -> String;
+
+	/// The fewest and the most players a game can be played with.
+	/// Rooms only vote to start early, if the game allows fewer players than seats.
 	fn get_player_bound(&self) -> (usize, usize);
+	/// The seats (player ids) of every team, players in the same team are partners
+	fn get_teams(&self) -> Vec<Vec<usize>>;
 	fn should_end(&self) -> bool;
 }
 
-#[derive(Clone, PartialEq, Eq)]
+#[derive(Clone, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
 pub enum RoomState {
+	#[default]
 	Entering,
 	Teaming,
 	Playing,
 	Ending,
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct RoomSetting<Game> {
+	/// Public rooms are listed in the room index
 	pub public: bool,
+	/// Clients have to know the password to join the room
+	#[serde(default)]
+	pub password: Option<PasswordHash>,
 	pub game_setting: Game,
 }
 
+/// How long the seat of a disconnected player is kept free for them
+pub const SEAT_RESERVATION: Duration = Duration::from_secs(120);
+
+/// How long a kicked client can't rejoin the room
+pub const KICK_BAN: Duration = Duration::from_secs(600);
+
+/// How many clients can watch a room at most
+pub const MAX_SPECTATORS: usize = 32;
+
+/// A seat kept for a disconnected client, until it reconnects or the reservation expires
+pub struct Reservation {
+	pub data: ClientData,
+	pub player_id: usize,
+	/// The vote of the player, if the room was restored during a vote
+	pub vote: Option<usize>,
+	pub expires: Instant,
+}
+
+impl Reservation {
+	pub fn is_valid(&self) -> bool {
+		Instant::now() < self.expires
+	}
+}
+
+/// A kicked client, which can't rejoin the room until the ban expires.
+///
+/// The address is banned as well, so dropping the session token doesn't help.
+/// Others connecting from the same address (e.g. behind the same router)
+/// can't join either, but may still reclaim their reserved seats.
+pub struct Ban {
+	pub session: SessionToken,
+	pub address: Option<IpAddr>,
+	pub until: Instant,
+}
+
+impl Ban {
+	fn applies_to(&self, client: &ClientData) -> bool {
+		client.session.as_ref() == Some(&self.session)
+			|| (self.address.is_some() && client.address == self.address)
+	}
+}
+
+/// How long a player has to act, before the game acts in their place
+pub const TURN_TIME: Duration = Duration::from_secs(60);
+
+/// Until when a player has to act
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Deadline {
+	pub plr_id: usize,
+	pub expires: Instant,
+}
+
+impl Deadline {
+	pub fn new(plr_id: usize, duration: Duration) -> Self {
+		Self {
+			plr_id,
+			expires: Instant::now() + duration,
+		}
+	}
+
+	/// Remaining whole seconds until the deadline expires
+	pub fn remaining_secs(&self) -> u64 {
+		self.expires
+			.saturating_duration_since(Instant::now())
+			.as_secs()
+	}
+}
+
+fn new_session_token() -> SessionToken {
+	rand::thread_rng()
+		.sample_iter(&Alphanumeric)
+		.take(32)
+		.map(char::from)
+		.collect()
+}
+
 pub struct Room<S, E, G>
 where
 	S: Clone,
@@ -50,14 +182,42 @@ where
 	G: ServerRoom<E> + TryFrom<S>,
 {
 	_marker: PhantomData<E>,
+	pub id: RoomID,
 	pub clients: ClientHandler,
 
 	pub setting: RoomSetting<S>,
 	pub game: G,
 	pub state: RoomState,
+	/// Number of seats, which is less than the maximum if the players voted to start early
+	pub num_players: usize,
 
 	pub num_votes: usize,
 	pub vote: Option<VotingType>,
+
+	pub reservations: HashMap<SessionToken, Reservation>,
+	/// The kicked clients
+	pub bans: Vec<Ban>,
+
+	/// The partner each client asked for while teaming
+	pub mates: HashMap<usize, usize>,
+
+	/// The deadline of the player on turn, which was announced to the clients
+	pub deadline: Option<Deadline>,
+
+	/// The bot players, by their client id
+	pub bots: HashMap<usize, Box<dyn Bot<G, E>>>,
+
+	/// When the clients were pinged the last time
+	pub last_heartbeat: Instant,
+
+	/// What the lobby shows of the room, so it doesn't have to lock the room
+	pub listing: ListingRef,
+	/// Set once the room is cleaned up, nobody can join it anymore
+	pub closed: bool,
+	/// When the room was created, in seconds since the unix epoch
+	pub created: i64,
+	/// Where changes of the listing are announced, set once the room is registered
+	pub lobby: Option<broadcast::Sender<LobbyEvent>>,
 }
 
 pub type RoomRef<S, E, G> = Arc<Mutex<Room<S, E, G>>>;
@@ -65,102 +225,417 @@ pub type RoomRef<S, E, G> = Arc<Mutex<Room<S, E, G>>>;
 impl<S, E, G> Room<S, E, G>
 where
 	S: Clone,
-	E: Clone + Serialize,
+	E: Clone + Serialize + DeserializeOwned,
 	G: ServerRoom<E> + Send + TryFrom<S>,
 {
-	pub fn try_new(setting: RoomSetting<S>) -> Result<Self, ()> {
+	#[allow(clippy::result_unit_err)]
+	pub fn try_new(id: RoomID, setting: RoomSetting<S>) -> Result<Self, ()> {
 		let game = G::try_from(setting.game_setting.clone()).map_err(|_| ())?;
+		let (_, num_players) = game.get_player_bound();
 
 		let res = Room {
 			_marker: PhantomData,
+			id,
 			clients: ClientHandler::default(),
 
 			game,
 			setting,
 			state: RoomState::Entering,
+			num_players,
 
 			num_votes: 0,
 			vote: None,
+
+			reservations: HashMap::new(),
+			bans: Vec::new(),
+
+			mates: HashMap::new(),
+
+			deadline: None,
+			bots: HashMap::new(),
+
+			last_heartbeat: Instant::now(),
+
+			listing: ListingRef::default(),
+			closed: false,
+			created: chrono::Utc::now().timestamp(),
+			lobby: None,
 		};
 
 		Ok(res)
 	}
 
+	/// Update the listing of the room with the current players,
+	/// the lobby is told if anything changed
+	pub fn publish(&self) {
+		let index = RoomIndex::new(self.id.clone(), self);
+
+		let mut listing = self.listing.write().unwrap();
+		let changed = listing.index != index;
+		listing.index = index;
+
+		if !changed || !listing.index.public || self.closed {
+			return;
+		}
+		if let Some(lobby) = &self.lobby {
+			// Nobody may be in the lobby, that's fine
+			let _ = lobby.send(LobbyEvent::RoomUpdated(listing.index.clone()));
+		}
+	}
+
 	pub async fn cleanup(&mut self) {
-		let futures = self.clients.iter_mut().map(|(_, client)| client.close());
+		let futures = self
+			.clients
+			.clients
+			.values_mut()
+			.chain(self.clients.spectators.values_mut())
+			.map(|client| client.close());
 
 		futures::future::join_all(futures).await;
 		self.clients.clear();
-	}
+		self.clients.spectators.clear();
+		self.reservations.clear();
+		self.bots.clear();
 
-	async fn check_active_clients(&mut self) {
-		let mut ids = Vec::<usize>::new();
-		let mut futures = vec![];
+		self.closed = true;
+		self.publish();
+	}
 
-		for (id, client) in self.clients.iter_mut() {
-			ids.push(*id);
-			futures.push(client.is_active());
+	/// The seats, which are neither taken nor reserved
+	fn free_seats(&self) -> Vec<usize> {
+		let mut mex = vec![true; self.num_players];
+		for (_, client) in self.clients.iter() {
+			mex[client.player_id] = false;
 		}
-
-		let results = futures::future::join_all(futures).await;
-
-		let iter = std::iter::zip(ids, results);
-		for (id, _) in iter.filter(|(_, active)| !active) {
-			debug!("Unregister {} due to inactivity.", id);
-			self.unregister(id).await;
+		for reservation in self.reservations.values().filter(|r| r.is_valid()) {
+			mex[reservation.player_id] = false;
 		}
+		(0..self.num_players).filter(|id| mex[*id]).collect()
 	}
 
 	/// Returns a player ID, if one exists
 	fn get_unused_player_id(&self) -> Option<usize> {
-		let (_, num_players) = self.game.get_player_bound();
+		self.free_seats().first().copied()
+	}
 
-		let mut mex = vec![true; num_players];
-		for (_, client) in self.clients.iter() {
-			mex[client.player_id] = false;
+	/// The client id of the bot, which took over the seat of the given session
+	fn bot_with_session(&self, session: &SessionToken) -> Option<usize> {
+		self.clients
+			.iter()
+			.find(|(_, client)| client.is_bot() && &client.session == session)
+			.map(|(id, _)| *id)
+	}
+
+	/// Returns the reservation of the given session and drops it
+	async fn reclaim_seat(&mut self, session: &SessionToken) -> Option<Reservation> {
+		// The old connection may not have noticed the disconnect yet,
+		// or a bot has taken over the seat in the meantime
+		let stale = self
+			.clients
+			.iter()
+			.find(|(_, client)| &client.session == session)
+			.map(|(id, _)| *id);
+
+		if let Some(client_id) = stale {
+			debug!(room = self.id, client = client_id; "Replace stale client");
+			self.unregister(client_id).await;
+		}
+
+		self.reservations.remove(session).filter(|r| r.is_valid())
+	}
+
+	/// Check whether the client may join the room, either on a seat or as spectator
+	pub async fn admit(&mut self, client: &ClientData) -> Result<(), CloseReason> {
+		// The room was closed, while the client was looking it up
+		if self.closed {
+			return Err(CloseReason::RoomNotFound);
+		}
+		if client.name.is_empty() {
+			return Err(CloseReason::EmptyName);
+		}
+
+		if let Some(password) = &self.setting.password {
+			if !client.secret.as_ref().is_some_and(|s| password.verify(s)) {
+				return Err(CloseReason::WrongPassword);
+			}
+		}
+
+		let reserved = client.session.as_ref().is_some_and(|session| {
+			self.reservations.get(session).is_some_and(|r| r.is_valid())
+				|| self.bot_with_session(session).is_some()
+		});
+
+		self.bans.retain(|ban| Instant::now() < ban.until);
+		for ban in self.bans.iter().filter(|ban| ban.applies_to(client)) {
+			// Others from the same address may return to their seats
+			if !reserved || client.session.as_ref() == Some(&ban.session) {
+				return Err(CloseReason::Banned);
+			}
+		}
+
+		if self.clients.spectators.len() < MAX_SPECTATORS {
+			return Ok(());
+		}
+		if client.spectate {
+			return Err(CloseReason::RoomFull);
+		}
+
+		if reserved || self.get_unused_player_id().is_some() {
+			Ok(())
+		} else {
+			Err(CloseReason::RoomFull)
 		}
-		mex.into_iter().position(|r| r)
 	}
 
-	/// Register a new client given the SplitSink
+	/// Register a new client sending over the given transport, the client has to be admitted first
 	pub async fn register(
 		&mut self,
 		client: ClientData,
-		ws_tx: WsWriter,
-	) -> Option<(ConnectionRef, usize)> {
+		transport: Box<dyn Transport>,
+	) -> (ConnectionRef, usize) {
+		if client.spectate {
+			return self.register_spectator(client, transport).await;
+		}
+
+		self.reservations.retain(|_, r| r.is_valid());
+
+		let reserved = match &client.session {
+			Some(session) => self.reclaim_seat(session).await,
+			None => None,
+		};
+
+		let (plr_id, session, vote) = match reserved {
+			Some(r) => (
+				r.player_id,
+				client.session.clone().unwrap_or_default(),
+				r.vote,
+			),
+			None => match self.get_unused_player_id() {
+				Some(id) => (id, new_session_token(), None),
+				None => return self.register_spectator(client, transport).await,
+			},
+		};
+
+		let (conn, id) = self
+			.clients
+			.register(client.clone(), plr_id, session.clone(), transport);
+
+		if let (Some(_), Some(vote)) = (&self.vote, vote) {
+			if let Some(client) = self.clients.get_mut(&id) {
+				client.vote = Some(vote);
+				self.num_votes += 1;
+			}
+		}
+		let num_players = self.num_players;
+
+		self.clients
+			.send_to(id, PlayerID::<E>(id, plr_id, num_players, session))
+			.await;
+		self.clients
+			.send_to_all_except(id, ClientJoined::<E>(client, id, plr_id))
+			.await;
+		self.introduce_room(id).await;
+
+		self.game.on_enter(&mut self.clients, plr_id).await;
+		self.check_start().await;
+		self.publish();
+
+		(conn, id)
+	}
+
+	/// Start the game if every seat is taken, or let the players vote if enough are there
+	async fn check_start(&mut self) {
+		if self.state != RoomState::Entering {
+			return;
+		}
+
+		let (low, up) = self.game.get_player_bound();
+		let num_connected = self.clients.len();
+
+		if num_connected == up {
+			self.quit_vote().await;
+			self.start_teaming().await;
+		} else if low <= num_connected {
+			// Don't interrupt other votes, like kicking someone
+			if matches!(self.vote, None | Some(VotingType::StartGame)) {
+				self.start_vote(VotingType::StartGame).await;
+			}
+		}
+	}
+
+	/// The human client, which is in the room the longest
+	fn host(&self) -> Option<usize> {
+		self.clients
+			.iter()
+			.filter(|(_, client)| !client.is_bot())
+			.map(|(id, _)| *id)
+			.min()
+	}
+
+	/// Seat a bot on the given seat without telling anyone, returns its client id
+	fn register_bot(&mut self, plr_id: usize, session: SessionToken) -> Option<usize> {
+		let bot = self.game.new_bot()?;
+		let data = ClientData {
+			name: format!("Bot {}", plr_id + 1),
+			session: None,
+			secret: None,
+			version: None,
+			spectate: false,
+			bot: true,
+			address: None,
+		};
+
+		// Bots look at the game instead of reading messages
+		let (_, id) = self
+			.clients
+			.register(data, plr_id, session, Box::new(NullTransport));
+		self.bots.insert(id, bot);
+
+		Some(id)
+	}
+
+	/// Seat a bot on the given seat, returns its client id
+	async fn seat_bot(&mut self, plr_id: usize, session: SessionToken) -> Option<usize> {
+		let id = self.register_bot(plr_id, session)?;
+		let data = self.clients[&id].data.clone();
+
+		self.clients
+			.send_to_all_except(id, ClientJoined::<E>(data, id, plr_id))
+			.await;
+		self.game.on_enter(&mut self.clients, plr_id).await;
+
+		Some(id)
+	}
+
+	/// Seat a bot on a free seat, if the host asks for it before the game starts
+	async fn add_bot(&mut self, client_id: usize) {
+		if self.state != RoomState::Entering || self.host() != Some(client_id) {
+			return;
+		}
+
 		let plr_id = match self.get_unused_player_id() {
 			Some(id) => id,
-			None => {
-				self.check_active_clients().await;
-				match self.get_unused_player_id() {
-					Some(id) => id,
-					None => return None,
-				}
-			}
+			None => return,
 		};
 
-		if client.name.is_empty() {
-			return None;
+		if let Some(id) = self.seat_bot(plr_id, new_session_token()).await {
+			info!(room = self.id, client = id, player = plr_id; "Add bot");
+			self.check_start().await;
 		}
+	}
 
-		let joined_clients = self
-			.clients
+	/// Let bots take over the seats, whose players didn't return in time.
+	/// The players can still reclaim their seat with their session.
+	async fn replace_abandoned(&mut self) {
+		// Nobody would be left to play with the bots
+		if self.host().is_none() {
+			return;
+		}
+
+		let expired: Vec<SessionToken> = self
+			.reservations
 			.iter()
-			.map(|(i, client)| (client.data.clone(), *i, client.player_id))
+			.filter(|(_, r)| !r.is_valid())
+			.map(|(session, _)| session.clone())
 			.collect();
 
-		let (conn, id) = self.clients.register(client.clone(), plr_id, ws_tx);
-		let (_, num_players) = self.game.get_player_bound();
+		for session in expired {
+			let plr_id = match self.reservations.remove(&session) {
+				Some(r) => r.player_id,
+				None => continue,
+			};
+
+			// Someone else may have taken the seat already
+			if self
+				.clients
+				.values()
+				.any(|client| client.player_id == plr_id)
+			{
+				continue;
+			}
+
+			if let Some(id) = self.seat_bot(plr_id, session).await {
+				info!(room = self.id, client = id, player = plr_id; "Bot takes over abandoned seat");
+			}
+		}
+	}
+
+	/// Let every bot act, if it wants to
+	async fn run_bots(&mut self) {
+		let ids: Vec<usize> = self.bots.keys().copied().collect();
+
+		for client_id in ids {
+			// A bot may have ended the game
+			if self.state != RoomState::Playing {
+				return;
+			}
+
+			let plr_id = match self.clients.get(&client_id) {
+				Some(client) => client.player_id,
+				None => continue,
+			};
+
+			let ev = match self.bots.get_mut(&client_id) {
+				Some(bot) => bot.act(&self.game, plr_id),
+				None => continue,
+			};
+
+			if let Some(ev) = ev {
+				let peer = Peer::Client(client_id, Some(plr_id));
+				self.clients.watch.record(Direction::Incoming, peer, || {
+					serde_json::to_string(&Event::<&E>(&ev)).unwrap()
+				});
+				self.handle_event(ev, client_id, plr_id).await;
+			}
+		}
+	}
+
+	/// Register a client, which only watches the game
+	async fn register_spectator(
+		&mut self,
+		client: ClientData,
+		transport: Box<dyn Transport>,
+	) -> (ConnectionRef, usize) {
+		let (conn, id) =
+			self.clients
+				.register_spectator(client.clone(), new_session_token(), transport);
+		let num_players = self.num_players;
 
 		self.clients
-			.send_to(id, PlayerID::<E>(id, plr_id, num_players))
+			.send_to(id, SpectatorID::<E>(id, num_players))
 			.await;
 		self.clients
-			.send_to_all_except(id, ClientJoined::<E>(client, id, plr_id))
+			.send_to_all_except(id, SpectatorJoined::<E>(client, id))
 			.await;
+		self.introduce_room(id).await;
+
+		self.game.on_spectate(&mut self.clients, id).await;
+		self.publish();
+
+		(conn, id)
+	}
+
+	/// Tell a newly joined client who is already in the room and what is being voted on
+	async fn introduce_room(&mut self, client_id: usize) {
+		let joined_clients = self
+			.clients
+			.iter()
+			.filter(|(i, _)| **i != client_id)
+			.map(|(i, client)| (client.data.clone(), *i, client.player_id))
+			.collect();
+		let joined_spectators = self
+			.clients
+			.spectators
+			.iter()
+			.filter(|(i, _)| **i != client_id)
+			.map(|(i, client)| (client.data.clone(), *i))
+			.collect();
 
 		self.clients
-			.send_to(id, JoinedClients::<E>(joined_clients))
+			.send_to(client_id, JoinedClients::<E>(joined_clients))
+			.await;
+		self.clients
+			.send_to(client_id, JoinedSpectators::<E>(joined_spectators))
 			.await;
 
 		if let Some(vote) = self.vote.clone() {
@@ -171,53 +646,92 @@ where
 				.map(|(i, c)| (c.vote.unwrap(), *i))
 				.collect();
 			self.clients
-				.send_to(id, CurrentVote::<E>(vote, votes))
+				.send_to(client_id, CurrentVote::<E>(vote, votes))
 				.await;
 		}
 
-		self.game.on_enter(&mut self.clients, plr_id).await;
-
-		if self.state == RoomState::Entering {
-			let (low, up) = self.game.get_player_bound();
-			let num_connected = self.clients.len();
-
-			if num_connected == up {
-				self.quit_vote().await;
-				let _ = self.game.start(&mut self.clients).await;
-				self.state = RoomState::Playing;
-			} else if low <= num_connected {
-				self.start_vote(VotingType::StartGame).await;
-			}
+		if let Some(deadline) = self.deadline {
+			let msg = TurnDeadline::<E>(Some((deadline.plr_id, deadline.remaining_secs())));
+			self.clients.send_to(client_id, msg).await;
 		}
-
-		Some((conn, id))
 	}
 
 	/// Unregister the client with the given id
 	pub async fn unregister(&mut self, client_id: usize) {
-		if self.vote.is_some() {
-			if let Some(client) = self.clients.get(&client_id) {
-				if client.vote.is_some() {
-					self.num_votes -= 1;
-					self.evaluate_vote().await;
-				}
-			}
+		if let Some(mut spectator) = self.clients.spectators.remove(&client_id) {
+			spectator.close().await;
+			self.clients
+				.send_to_all(ClientDisconnected::<E>(client_id))
+				.await;
+			self.publish();
+			return;
 		}
 
-		let pid = if let Some(client) = self.clients.get_mut(&client_id) {
-			client.close().await;
-			Some(client.player_id)
-		} else {
-			None
+		let mut client = match self.clients.remove(&client_id) {
+			Some(client) => client,
+			// Already gone, e.g. kicked or dropped before its socket closed
+			None => return,
 		};
-		self.clients.remove(&client_id);
+		client.close().await;
+		self.bots.remove(&client_id);
 
-		if let Some(id) = pid {
-			let _ = self.game.on_leave(&mut self.clients, id).await;
+		if self.vote.is_some() && client.vote.is_some() {
+			self.num_votes -= 1;
 		}
+
+		let plr_id = client.player_id;
+		// Keep the seat for the player, so they don't lose their cards
+		if matches!(self.state, RoomState::Playing | RoomState::Ending) {
+			let reservation = Reservation {
+				data: client.data,
+				player_id: plr_id,
+				vote: None,
+				expires: Instant::now() + SEAT_RESERVATION,
+			};
+			self.reservations.insert(client.session, reservation);
+		}
+
+		let _ = self.game.on_leave(&mut self.clients, plr_id).await;
 		self.clients
 			.send_to_all(ClientDisconnected::<E>(client_id))
 			.await;
+
+		// The room isn't full anymore, so wait for new players
+		if self.state == RoomState::Teaming {
+			self.reopen_seats().await;
+		}
+
+		let (low, _) = self.game.get_player_bound();
+		let too_few = self.vote == Some(VotingType::StartGame) && self.clients.len() < low;
+
+		if self.vote == Some(VotingType::Kick(client_id)) || too_few {
+			self.quit_vote().await;
+		} else {
+			self.evaluate_vote().await;
+		}
+		self.publish();
+	}
+
+	/// Remove a client from the room and ban its session and address for a while
+	pub async fn kick(&mut self, client_id: usize) {
+		let (session, address, bot) = match self.clients.get(&client_id) {
+			Some(client) => (client.session.clone(), client.data.address, client.is_bot()),
+			None => return,
+		};
+
+		info!(room = self.id, client = client_id; "Kick client");
+		// A bot may hold the session of the player, whose seat it took over
+		if !bot {
+			self.bans.push(Ban {
+				session: session.clone(),
+				address,
+				until: Instant::now() + KICK_BAN,
+			});
+		}
+
+		// Boxed, since unregistering may evaluate votes again
+		Box::pin(self.unregister(client_id)).await;
+		self.reservations.remove(&session);
 	}
 
 	/// Start a new vote
@@ -232,7 +746,7 @@ where
 
 	/// Quit the current vote
 	async fn quit_vote(&mut self) {
-		if let Some(_) = self.vote {
+		if self.vote.is_some() {
 			self.clients.send_to_all(SocketMessage::<E>::QuitVote).await;
 		}
 		self.vote = None;
@@ -241,23 +755,35 @@ where
 	/// Ends the current game
 	async fn end_game(&mut self) {
 		if self.state != RoomState::Ending {
-			debug!("End game");
+			debug!(room = self.id; "End game");
 			self.start_vote(VotingType::Revanche).await;
 			self.state = RoomState::Ending;
 		}
 	}
 
+	/// Number of clients allowed to take part in the current vote, bots don't vote
+	fn num_voters(&self) -> usize {
+		let humans = self.clients.iter().filter(|(_, client)| !client.is_bot());
+		match self.vote {
+			Some(VotingType::Kick(target)) => humans.filter(|(&id, _)| id != target).count(),
+			_ => humans.count(),
+		}
+	}
+
 	async fn evaluate_vote(&mut self) {
-		let vote = match &self.vote {
+		let num_voters = self.num_voters();
+		let vote = match self.vote.clone() {
 			Some(v) => v,
 			None => return,
 		};
 
-		if self.num_votes != self.clients.len() {
+		if num_voters == 0 || self.num_votes != num_voters {
 			return;
 		}
 
-		debug!("Evaluate: {:?}", vote);
+		debug!(room = self.id; "Evaluate: {:?}", vote);
+		// Quit first, the outcome may start the next vote
+		self.quit_vote().await;
 
 		match vote {
 			VotingType::Revanche => {
@@ -272,31 +798,72 @@ where
 					.filter(|(_, c)| c.vote == Some(1))
 					.count();
 
-				if agree > decline {
-					let _ = self.game.start(&mut self.clients).await;
-					self.state = RoomState::Playing;
+				if agree > decline && self.num_players < self.game.get_player_bound().1 {
+					// The last game started early, so give newcomers a chance to join
+					self.reopen_seats().await;
+					self.check_start().await;
+				} else if agree > decline {
+					self.start_game().await;
 				} else {
 					self.cleanup().await;
 				}
 			}
-			VotingType::Teaming => self.handle_team_choosing().await,
-			_ => todo!("Not implemented yet..."),
-		}
+			VotingType::StartGame => {
+				let agree: usize = self
+					.clients
+					.iter()
+					.filter(|(_, c)| c.vote == Some(0))
+					.count();
 
-		self.quit_vote().await;
-	}
+				let (low, _) = self.game.get_player_bound();
+				let enough = low <= self.clients.len();
 
-	async fn handle_vote(&mut self, vote: usize, client_id: usize) {
-		match self.clients.get_mut(&client_id) {
-			Some(client) => {
-				if client.vote.is_some() {
-					return;
+				if self.state == RoomState::Entering && enough && num_voters < 2 * agree {
+					self.compact_seats().await;
+					self.start_teaming().await;
 				}
-				client.vote = Some(vote);
 			}
-			None => return,
-		}
-
+			VotingType::Kick(target) => {
+				let agree: usize = self
+					.clients
+					.iter()
+					.filter(|(_, c)| c.vote == Some(0))
+					.count();
+
+				if 2 * agree > num_voters {
+					self.kick(target).await;
+				}
+			}
+			VotingType::Teaming => self.handle_team_choosing().await,
+		}
+	}
+
+	/// Start a vote to kick the target, the proposing client agrees right away
+	async fn propose_kick(&mut self, target: usize, client_id: usize) {
+		if self.vote.is_some() || target == client_id || !self.clients.contains_key(&target) {
+			return;
+		}
+
+		self.start_vote(VotingType::Kick(target)).await;
+		self.handle_vote(0, client_id).await;
+	}
+
+	async fn handle_vote(&mut self, vote: usize, client_id: usize) {
+		// The target of a kick vote has no say in it
+		if self.vote.is_none() || self.vote == Some(VotingType::Kick(client_id)) {
+			return;
+		}
+
+		match self.clients.get_mut(&client_id) {
+			Some(client) => {
+				if client.vote.is_some() {
+					return;
+				}
+				client.vote = Some(vote);
+			}
+			None => return,
+		}
+
 		self.clients
 			.send_to_all_except(client_id, Vote::<E>(vote, client_id))
 			.await;
@@ -305,56 +872,285 @@ where
 		self.evaluate_vote().await;
 	}
 
+	/// Let the players choose their partners, or start right away if the game has no teams
+	async fn start_teaming(&mut self) {
+		let has_teams = self.get_teams().iter().any(|team| 1 < team.len());
+
+		if !has_teams {
+			self.start_game().await;
+			return;
+		}
+
+		self.state = RoomState::Teaming;
+		self.mates.clear();
+		self.clients.send_to_all(StartMating::<E>).await;
+		self.start_vote(VotingType::Teaming).await;
+	}
+
+	/// Remember whom the client wants to partner with and tell the requested client
+	async fn handle_mate(&mut self, mate: usize, client_id: usize) {
+		if self.state != RoomState::Teaming || !self.clients.contains_key(&mate) {
+			return;
+		}
+
+		self.mates.insert(client_id, mate);
+		if mate != client_id {
+			self.clients.send_to(mate, Mate::<E>(client_id)).await;
+		}
+	}
+
 	async fn handle_team_choosing(&mut self) {
 		if self.state != RoomState::Teaming {
 			return;
 		}
-		let (_, num_players) = self.game.get_player_bound();
 
-		// TODO Correctly handle team choosing
-		// TODO This is merely shuffling, actually handle the requests
-		let players = {
-			let mut rng = rand::thread_rng();
-			let mut v: Vec<usize> = (0..num_players).collect();
-			v.shuffle(&mut rng);
-			v
-		};
+		let ids: Vec<usize> = self.clients.keys().copied().collect();
+		let teams = self.get_teams();
+		let seating = teaming::resolve_seating(&ids, &self.mates, &teams);
+
+		for (id, client) in self.clients.iter_mut() {
+			if let Some(&plr_id) = seating.get(id) {
+				client.player_id = plr_id;
+			}
+		}
+		self.mates.clear();
 
-		let order = self
+		let order = seating.into_iter().collect();
+		self.clients.send_to_all(PlayerOrder::<E>(order)).await;
+
+		self.start_game().await;
+	}
+
+	/// The teams of the game, restricted to the seats in use
+	fn get_teams(&self) -> Vec<Vec<usize>> {
+		self.game
+			.get_teams()
+			.into_iter()
+			.map(|team| {
+				team.into_iter()
+					.filter(|&id| id < self.num_players)
+					.collect()
+			})
+			.collect()
+	}
+
+	/// Give the connected players the seats 0 to n-1, so the game can start without the others
+	async fn compact_seats(&mut self) {
+		let mut seats: Vec<(usize, usize)> = self
 			.clients
 			.iter()
-			.map(|(i, _)| *i)
+			.map(|(id, client)| (client.player_id, *id))
+			.collect();
+		seats.sort();
+
+		let order: Vec<(usize, usize)> = seats
+			.into_iter()
 			.enumerate()
-			.map(|(i, cid)| (cid, players[i]))
+			.map(|(plr_id, (_, id))| (id, plr_id))
 			.collect();
 
-		self.clients.send_to_all(PlayerOrder::<E>(order)).await;
-		let _ = self.game.start(&mut self.clients).await;
+		let mut changed = false;
+		for (id, plr_id) in order.iter() {
+			if let Some(client) = self.clients.get_mut(id) {
+				changed |= client.player_id != *plr_id;
+				client.player_id = *plr_id;
+			}
+		}
+
+		self.num_players = order.len();
+		if changed {
+			self.clients.send_to_all(PlayerOrder::<E>(order)).await;
+		}
+	}
+
+	/// Wait for players again, with every seat of the game
+	async fn reopen_seats(&mut self) {
+		let (_, num_players) = self.game.get_player_bound();
+		self.num_players = num_players;
+		self.state = RoomState::Entering;
+		self.mates.clear();
+
+		self.quit_vote().await;
+	}
+
+	async fn start_game(&mut self) {
+		let _ = self.game.start(&mut self.clients, self.num_players).await;
+		self.state = RoomState::Playing;
+		self.restart_turn().await;
+	}
+
+	/// The player on turn, while a game is running
+	fn on_turn(&self) -> Option<usize> {
+		match self.state {
+			RoomState::Playing => self.game.on_turn(),
+			_ => None,
+		}
+	}
+
+	/// Give the player on turn the full time again and tell the clients
+	async fn restart_turn(&mut self) {
+		let deadline = self
+			.on_turn()
+			.map(|plr_id| Deadline::new(plr_id, TURN_TIME));
+		if deadline == self.deadline {
+			return;
+		}
+
+		self.deadline = deadline;
+		let msg = deadline.map(|d| (d.plr_id, d.remaining_secs()));
+		self.clients.send_to_all(TurnDeadline::<E>(msg)).await;
+	}
+
+	/// Start the deadline again, if another player is on turn.
+	/// Also starts it after a restore, deadlines aren't saved in snapshots.
+	async fn update_deadline(&mut self) {
+		if self.on_turn() != self.deadline.map(|d| d.plr_id) {
+			self.restart_turn().await;
+		}
+	}
+
+	/// Unregister the clients, whose connection was dropped because they couldn't keep up
+	async fn remove_dropped(&mut self) {
+		let mut ids = vec![];
+		for (id, client) in self.clients.iter().chain(self.clients.spectators.iter()) {
+			if client.connection.lock().await.is_dropped() {
+				ids.push(*id);
+			}
+		}
+
+		for id in ids {
+			info!(room = self.id, client = id; "Drop client, which couldn't keep up");
+			self.unregister(id).await;
+		}
+	}
+
+	/// Drop the clients, which stopped answering, ping the others
+	/// and tell everyone the latency of the players
+	pub async fn heartbeat(&mut self, heartbeat: &Heartbeat) {
+		if self.last_heartbeat.elapsed() < heartbeat.interval {
+			return;
+		}
+		self.last_heartbeat = Instant::now();
+
+		let mut stale = vec![];
+		let mut latencies = vec![];
+		let everyone = self.clients.iter().chain(self.clients.spectators.iter());
+		for (id, client) in everyone.filter(|(_, client)| !client.is_bot()) {
+			let mut conn = client.connection.lock().await;
+			if heartbeat.timeout <= conn.last_response.elapsed() {
+				stale.push(*id);
+				continue;
+			}
+
+			if let (Some(latency), false) = (conn.latency, self.clients.is_spectator(*id)) {
+				latencies.push((*id, latency.as_millis() as u64));
+			}
+			conn.ping();
+		}
+
+		// Players, who stopped answering, keep their seat reserved
+		for id in stale {
+			info!(room = self.id, client = id; "Client stopped answering");
+			self.unregister(id).await;
+		}
+
+		if !latencies.is_empty() {
+			self.clients.send_to_all(Latency::<E>(latencies)).await;
+		}
+	}
+
+	/// Let the game know time has passed, let the bots act and
+	/// act for players, who missed their deadline
+	pub async fn tick(&mut self) {
+		self.remove_dropped().await;
+		self.run_bots().await;
+
+		if self.state != RoomState::Playing {
+			return;
+		}
+
+		self.replace_abandoned().await;
+
+		if let Some(deadline) = self.deadline {
+			if deadline.expires <= Instant::now() {
+				debug!(room = self.id, player = deadline.plr_id; "Deadline expired");
+				self.game
+					.on_timeout(&mut self.clients, deadline.plr_id)
+					.await;
+				// Give the player another turn, if nothing could be done for them
+				self.restart_turn().await;
+			}
+		}
+
+		if self.game.should_end() {
+			self.end_game().await;
+		}
+		self.update_deadline().await;
 	}
 
 	pub fn is_full(&self) -> bool {
-		self.clients.len() == self.game.get_player_bound().1
+		self.get_unused_player_id().is_none()
 	}
 
+	/// The room closes once no human is left, the bots don't play on their own
 	pub fn should_close(&self) -> bool {
-		self.clients.is_empty()
+		self.host().is_none() && !self.reservations.values().any(|r| r.is_valid())
 	}
 
-	async fn handle_event(&mut self, ev: E, plr_id: usize) {
-		let _ = self.game.on_event(&mut self.clients, ev, plr_id).await;
+	async fn handle_event(&mut self, ev: E, client_id: usize, plr_id: usize) {
+		let ends_turn = self.game.ends_turn(&ev);
+		match self.game.on_event(&mut self.clients, ev, plr_id).await {
+			Ok(()) if ends_turn => self.restart_turn().await,
+			Ok(()) => {}
+			Err(e) => {
+				debug!(room = self.id, client = client_id, player = plr_id; "Event was rejected");
+				self.clients
+					.send_to(client_id, ErrorMessage::Error(e))
+					.await;
+			}
+		}
 		if self.game.should_end() {
 			self.end_game().await;
 		}
+		self.update_deadline().await;
+	}
+
+	/// Record a frame of the client, which was dropped before it reached the room
+	pub fn record_rejected(&self, client_id: usize, reason: &str, frame: &str) {
+		let plr_id = self.clients.get(&client_id).map(|client| client.player_id);
+		let direction = Direction::Rejected(reason.to_string());
+		self.clients
+			.watch
+			.record(direction, Peer::Client(client_id, plr_id), || {
+				frame.to_string()
+			});
 	}
 
 	pub async fn handle_input(&mut self, input: SocketMessage<E>, client_id: usize) {
+		let plr_id = self.clients.get(&client_id).map(|client| client.player_id);
+		self.clients
+			.watch
+			.record(Direction::Incoming, Peer::Client(client_id, plr_id), || {
+				serde_json::to_string(&input).unwrap()
+			});
+
+		// Spectators may only chat
+		if self.clients.is_spectator(client_id) {
+			if let ChatMessage(text, _) = input {
+				self.clients
+					.send_to_all(ChatMessage::<E>(text, client_id))
+					.await;
+			}
+			return;
+		}
+
 		let plr_id = match self.clients.get(&client_id) {
 			Some(client) => client.player_id,
 			None => return,
 		};
 
 		match input {
-			Event(ev) => self.handle_event(ev, plr_id).await,
+			Event(ev) => self.handle_event(ev, client_id, plr_id).await,
 			RtcStart(_) => {
 				self.clients
 					.send_to_all_except(client_id, RtcStart::<E>(client_id))
@@ -371,8 +1167,11 @@ where
 					.await;
 			}
 			Vote(opt, _) => self.handle_vote(opt, client_id).await,
+			NewVote(VotingType::Kick(target)) => self.propose_kick(target, client_id).await,
+			Mate(mate) => self.handle_mate(mate, client_id).await,
+			AddBot => self.add_bot(client_id).await,
 			_ => {
-				error!("Invalid header!");
+				warn!(room = self.id, client = client_id; "Invalid header!");
 			}
 		}
 	}
@@ -380,6 +1179,18 @@ where
 
 pub type RoomID = String;
 
+/// Characters of generated room ids, without easily confused ones like 0/O or 1/I
+pub const ROOM_ID_ALPHABET: &str = "ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+/// Length of generated ids of public rooms
+pub const ROOM_ID_LENGTH: usize = 5;
+/// How often to retry generating a room id, when it is already used
+const ROOM_ID_ATTEMPTS: usize = 16;
+
+/// Number of rooms in a page of the room index, if not asked for another size
+pub const INDEX_PAGE: usize = 32;
+/// Most rooms returned at once by the room index
+pub const MAX_INDEX_PAGE: usize = 128;
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum ServerRequest {
 	CloseRoom(RoomID),
@@ -389,6 +1200,14 @@ pub enum ServerRequest {
 
 	SaveToFile(String),
 	LoadFromFile(String),
+
+	InspectRoom(RoomID),
+	/// Remove the client with the given id from the room, players are banned for a while
+	Kick(RoomID, usize),
+	/// Show a notice to every client in every room
+	Broadcast(String),
+	/// Stream the messages of the room as `Watched` answers, until the connection is closed
+	Watch(RoomID),
 }
 
 #[derive(Serialize, Deserialize)]
@@ -396,6 +1215,43 @@ pub enum ServerAnswer {
 	Successful,
 	Unsuccessful,
 	RoomList(Vec<RoomIndex>),
+	/// The request could not be handled, e.g. because the server doesn't know it
+	Error(String),
+	Details(RoomDetails),
+	Watched(WatchEvent),
+}
+
+/// A client as seen by the admin
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ClientInfo {
+	pub id: usize,
+	pub name: String,
+	/// None for spectators
+	pub player_id: Option<usize>,
+	pub bot: bool,
+	pub vote: Option<usize>,
+	pub latency_ms: Option<u64>,
+}
+
+/// Everything the admin may want to know about a single room
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RoomDetails {
+	pub id: RoomID,
+	pub state: RoomState,
+	pub public: bool,
+	pub password: bool,
+	pub num_players: usize,
+
+	pub players: Vec<ClientInfo>,
+	pub spectators: Vec<ClientInfo>,
+	/// Names of the disconnected players, whose seat is still kept
+	pub reservations: Vec<(String, usize)>,
+
+	pub vote: Option<VotingType>,
+	pub num_votes: usize,
+
+	/// The game settings as JSON
+	pub game_setting: String,
 }
 
 pub struct RoomManager<S, E, G>
@@ -404,172 +1260,491 @@ where
 	E: Clone + Send,
 	G: ServerRoom<E> + TryFrom<S> + Send,
 {
-	room_next: u32,
-	rooms: HashMap<RoomID, RoomRef<S, E, G>>,
+	rooms: Registry<RoomEntry<S, E, G>>,
+	lobby: broadcast::Sender<LobbyEvent>,
+
+	id_alphabet: Vec<char>,
+	id_length: usize,
+
+	heartbeat: Heartbeat,
+}
+
+/// A registered room together with its listing, which can be read without locking the room
+struct RoomEntry<S, E, G>
+where
+	S: Clone,
+	E: Clone,
+	G: ServerRoom<E> + TryFrom<S>,
+{
+	room: RoomRef<S, E, G>,
+	listing: ListingRef,
+}
+
+impl<S, E, G> Clone for RoomEntry<S, E, G>
+where
+	S: Clone,
+	E: Clone,
+	G: ServerRoom<E> + TryFrom<S>,
+{
+	fn clone(&self) -> Self {
+		Self {
+			room: self.room.clone(),
+			listing: self.listing.clone(),
+		}
+	}
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq)]
 pub struct RoomIndex {
+	/// Names of everyone seated, bots included
 	pub players: Vec<String>,
+	/// How many of the players are bots
+	pub bots: usize,
+	pub spectators: Vec<String>,
 	pub id: RoomID,
 	pub max_players: usize,
+
+	pub state: RoomState,
+	/// Description of the game settings, given by the game
+	pub summary: String,
+	/// When the room was created, in seconds since the unix epoch
+	pub created: i64,
+	pub num_spectators: usize,
+	/// Seats, which are neither taken nor reserved for a disconnected player
+	pub free_seats: usize,
+
+	/// Private rooms are only found by their id
+	pub public: bool,
+	/// Clients have to know the password to join the room
+	pub password: bool,
 }
 
 impl RoomIndex {
 	fn new<S, E, G>(id: RoomID, item: &Room<S, E, G>) -> Self
 	where
 		S: Clone,
-		E: Clone,
-		G: ServerRoom<E> + TryFrom<S>,
+		E: Clone + Serialize + DeserializeOwned,
+		G: ServerRoom<E> + Send + TryFrom<S>,
 	{
 		let names: Vec<_> = item
 			.clients
-			.iter()
-			.map(|(_, client)| client.data.name.clone())
+			.values()
+			.map(|client| client.data.name.clone())
+			.collect();
+		let spectators: Vec<_> = item
+			.clients
+			.spectators
+			.values()
+			.map(|client| client.data.name.clone())
 			.collect();
 
+		let bots = item
+			.clients
+			.values()
+			.filter(|client| client.is_bot())
+			.count();
+
 		Self {
 			id,
 			players: names,
-			max_players: item.game.get_player_bound().1,
+			bots,
+			num_spectators: spectators.len(),
+			spectators,
+			max_players: item.num_players,
+
+			state: item.state.clone(),
+			summary: item.game.summary(),
+			created: item.created,
+			free_seats: item.free_seats().len(),
+
+			public: item.setting.public,
+			password: item.setting.password.is_some(),
 		}
 	}
 }
 
+/// The state of a room as last published by it
+#[derive(Clone, Default)]
+pub struct Listing {
+	pub index: RoomIndex,
+}
+
+/// Query of the room index, e.g. `GET /rooms?joinable=true&min_players=1&offset=32`
+#[derive(Deserialize, Default)]
+pub struct RoomFilter {
+	/// Number of rooms to skip, for pagination
+	#[serde(default)]
+	pub offset: usize,
+	/// Number of rooms to return, at most `MAX_INDEX_PAGE`
+	pub limit: Option<usize>,
+
+	/// Only rooms with a free seat
+	#[serde(default)]
+	pub joinable: bool,
+	/// Bounds of the number of connected players, bots aren't counted
+	pub min_players: Option<usize>,
+	pub max_players: Option<usize>,
+	/// Only rooms with this many seats
+	pub seats: Option<usize>,
+}
+
+impl RoomFilter {
+	fn matches(&self, index: &RoomIndex) -> bool {
+		let players = index.players.len() - index.bots;
+
+		(!self.joinable || 0 < index.free_seats)
+			&& self.min_players.is_none_or(|min| min <= players)
+			&& self.max_players.is_none_or(|max| players <= max)
+			&& self.seats.is_none_or(|seats| seats == index.max_players)
+	}
+}
+
+pub type ListingRef = Arc<RwLock<Listing>>;
+
+impl<S, E, G> Room<S, E, G>
+where
+	S: Clone + Serialize,
+	E: Clone,
+	G: ServerRoom<E> + TryFrom<S>,
+{
+	pub async fn details(&mut self) -> RoomDetails {
+		let mut players = vec![];
+		let mut spectators = vec![];
+		let everyone = self.clients.iter().chain(self.clients.spectators.iter());
+		for (id, client) in everyone {
+			let spectator = self.clients.is_spectator(*id);
+			let latency = client.connection.lock().await.latency;
+
+			let info = ClientInfo {
+				id: *id,
+				name: client.data.name.clone(),
+				player_id: (!spectator).then_some(client.player_id),
+				bot: client.is_bot(),
+				vote: client.vote,
+				latency_ms: latency.map(|l| l.as_millis() as u64),
+			};
+			if spectator {
+				spectators.push(info);
+			} else {
+				players.push(info);
+			}
+		}
+		players.sort_by_key(|info| info.player_id);
+		spectators.sort_by_key(|info| info.id);
+
+		let reservations = self
+			.reservations
+			.values()
+			.filter(|r| r.is_valid())
+			.map(|r| (r.data.name.clone(), r.player_id))
+			.collect();
+
+		RoomDetails {
+			id: self.id.clone(),
+			state: self.state.clone(),
+			public: self.setting.public,
+			password: self.setting.password.is_some(),
+			num_players: self.num_players,
+
+			players,
+			spectators,
+			reservations,
+
+			vote: self.vote.clone(),
+			num_votes: self.num_votes,
+
+			game_setting: serde_json::to_string(&self.setting.game_setting).unwrap_or_default(),
+		}
+	}
+}
+
+impl<S, E, G> Default for RoomManager<S, E, G>
+where
+	S: Clone + Send,
+	E: Clone + Serialize + DeserializeOwned + Send,
+	G: ServerRoom<E> + TryFrom<S> + Send,
+{
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
 impl<S, E, G> RoomManager<S, E, G>
 where
 	S: Clone + Send,
-	E: Clone + Serialize + Send,
+	E: Clone + Serialize + DeserializeOwned + Send,
 	G: ServerRoom<E> + TryFrom<S> + Send,
 {
 	pub fn new() -> Self {
 		Self {
-			room_next: 0u32,
-			rooms: HashMap::new(),
+			rooms: Registry::new(),
+			lobby: broadcast::channel(LOBBY_CAPACITY).0,
+
+			id_alphabet: ROOM_ID_ALPHABET.chars().collect(),
+			id_length: ROOM_ID_LENGTH,
+
+			heartbeat: Heartbeat::default(),
 		}
 	}
 
-	pub fn create_room(&mut self, setting: RoomSetting<S>) -> Option<(String, RoomRef<S, E, G>)> {
-		// TODO create a decent room id generator
-		let id: RoomID = {
-			let number = {
-				const ORDER: [usize; 32] = [
-					8, 19, 2, 13, 1, 17, 7, 0, 4, 11, 12, 9, 15, 18, 16, 5, 14, 3, 6, 10, 20, 21,
-					22, 23, 24, 25, 26, 27, 28, 29, 30, 31,
-				];
+	/// Set the characters and the length of newly generated room ids
+	pub fn set_id_format(&mut self, alphabet: &str, length: usize) {
+		self.id_alphabet = alphabet.chars().collect();
+		self.id_length = length;
+	}
+
+	pub fn set_heartbeat(&mut self, heartbeat: Heartbeat) {
+		self.heartbeat = heartbeat;
+	}
 
-				let mut x: u32 = 0;
-				for (i, &j) in ORDER.iter().enumerate() {
-					x |= ((self.room_next >> j) & 1) << i;
-				}
-				x
-			};
+	/// Generate a random room id, which isn't used yet.
+	/// Private rooms get twice as long ids, so they can't be guessed.
+	fn generate_room_id(&self, private: bool) -> Option<RoomID> {
+		let length = if private {
+			2 * self.id_length
+		} else {
+			self.id_length
+		};
 
-			// custom base 32 encoding
-			let encode = {
-				const BASE: &[u8] = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdef".as_bytes();
+		let mut rng = rand::thread_rng();
+		for _ in 0..ROOM_ID_ATTEMPTS {
+			let id: RoomID = (0..length)
+				.filter_map(|_| self.id_alphabet.choose(&mut rng))
+				.collect();
 
-				let bytes: Vec<_> = (0..4)
-					.map(|x| {
-						let idx = (number >> 5 * x) & 0x1F;
-						BASE[idx as usize]
-					})
-					.collect();
+			if !id.is_empty() && !self.rooms.contains(&id) {
+				return Some(id);
+			}
+		}
 
-				RoomID::from_utf8_lossy(&bytes).into()
-			};
+		error!("Could not generate an unused room id!");
+		None
+	}
+
+	/// Register the room, returns None if its id is already taken
+	fn insert(&self, mut room: Room<S, E, G>) -> Option<RoomRef<S, E, G>> {
+		room.publish();
+		room.lobby = Some(self.lobby.clone());
 
-			self.room_next += 1;
-			encode
+		let id = room.id.clone();
+		let listing = room.listing.read().unwrap().clone();
+		let entry = RoomEntry {
+			listing: room.listing.clone(),
+			room: Arc::from(Mutex::from(room)),
 		};
 
+		let roomref = entry.room.clone();
+		if !self.rooms.try_insert(id, entry) {
+			return None;
+		}
+
+		if listing.index.public {
+			let _ = self.lobby.send(LobbyEvent::RoomCreated(listing.index));
+		}
+		Some(roomref)
+	}
+
+	/// Unregister the room and tell the lobby, the room still has to be cleaned up
+	fn remove(&self, id: &RoomID) -> Option<RoomEntry<S, E, G>> {
+		let entry = self.rooms.remove(id)?;
+
+		if entry.listing.read().unwrap().index.public {
+			let _ = self.lobby.send(LobbyEvent::RoomClosed(id.clone()));
+		}
+		Some(entry)
+	}
+
+	/// Listen to the changes of the public rooms
+	pub fn subscribe_lobby(&self) -> broadcast::Receiver<LobbyEvent> {
+		self.lobby.subscribe()
+	}
+
+	pub fn create_room(&self, setting: RoomSetting<S>) -> Option<(String, RoomRef<S, E, G>)> {
+		let id = self.generate_room_id(!setting.public)?;
+
 		// Handle if rooms are
-		let room = match Room::<S, E, G>::try_new(setting) {
+		let room = match Room::<S, E, G>::try_new(id.clone(), setting) {
 			Ok(r) => r,
 			Err(_) => return None,
 		};
 
-		let roomref = Arc::from(Mutex::from(room));
-
-		if self.rooms.contains_key(&id) {
+		// Another room may have taken the id in the meantime
+		let Some(roomref) = self.insert(room) else {
+			warn!(room = id; "Room id was taken while creating the room");
 			return None;
-		}
-		self.rooms.insert(id.clone(), roomref.clone());
+		};
 
-		debug!("Create room {}", id);
+		info!(room = id; "Create room");
 		Some((id, roomref))
 	}
 
-	pub async fn maintain_room(&mut self, id: &RoomID) {
-		let close = match self.rooms.get(id) {
-			Some(room) => {
-				let mut rlock = room.lock().await;
-				if rlock.should_close() {
-					rlock.cleanup().await;
-					true
-				} else {
-					false
-				}
-			}
-			None => false,
+	/// Close the room, if nobody is using it anymore
+	pub async fn maintain_room(&self, id: &RoomID) {
+		let room = match self.rooms.get(id) {
+			Some(entry) => entry.room,
+			None => return,
 		};
 
-		debug!("Close room {}", id);
-		if close {
-			self.rooms.remove(id);
+		let mut rlock = room.lock().await;
+		if !rlock.closed && rlock.should_close() {
+			rlock.cleanup().await;
+			self.remove(id);
+			info!(room = id; "Close room");
 		}
 	}
 
-	pub async fn maintain(&mut self) {
-		let rooms_futures = self.rooms.iter().map(|(id, room)| async move {
-			let mut rlock = room.lock().await;
-			if rlock.should_close() {
-				rlock.cleanup().await;
-				Some(id.clone())
-			} else {
-				None
-			}
-		});
+	pub async fn maintain(&self) {
+		let futures = self
+			.rooms
+			.entries()
+			.into_iter()
+			.map(|(id, _)| async move { self.maintain_room(&id).await });
 
-		let rooms = futures::future::join_all(rooms_futures).await;
+		futures::future::join_all(futures).await;
+	}
 
-		let to_close = rooms
+	/// Every public room, oldest first. Uses the listings, so no room is locked.
+	pub fn public_rooms(&self) -> Vec<RoomIndex> {
+		let mut rooms: Vec<_> = self
+			.rooms
+			.entries()
 			.into_iter()
-			.filter(|c| c.is_some())
-			.map(|c| c.unwrap());
+			.filter_map(|(_, entry)| {
+				let listing = entry.listing.read().unwrap();
+				listing.index.public.then(|| listing.index.clone())
+			})
+			.collect();
+		// The registry has no order, but the pages should be stable
+		rooms.sort_by(|a, b| (a.created, &a.id).cmp(&(b.created, &b.id)));
+		rooms
+	}
 
-		for id in to_close {
-			self.rooms.remove(&id);
-		}
+	/// The public rooms matching the filter, oldest first
+	pub fn index_rooms(&self, filter: &RoomFilter) -> Vec<RoomIndex> {
+		let limit = filter.limit.unwrap_or(INDEX_PAGE).min(MAX_INDEX_PAGE);
+
+		self.public_rooms()
+			.into_iter()
+			.filter(|index| filter.matches(index))
+			.skip(filter.offset)
+			.take(limit)
+			.collect()
 	}
 
-	pub async fn index_rooms(&self) -> Vec<RoomIndex> {
-		let mut v = vec![];
-		for (id, room) in self.rooms.iter() {
-			let rlock = room.lock().await;
-			if !rlock.is_full() {
-				v.push(RoomIndex::new(id.clone(), rlock.deref()));
+	/// The index of a single room, private rooms included
+	pub fn get_room_index(&self, id: &RoomID) -> Option<RoomIndex> {
+		let entry = self.rooms.get(id)?;
+		let index = entry.listing.read().unwrap().index.clone();
+		Some(index)
+	}
 
-				if v.len() >= 32 {
-					break;
-				}
+	pub fn get_room(&self, id: &RoomID) -> Option<RoomRef<S, E, G>> {
+		self.rooms.get(id).map(|entry| entry.room)
+	}
+
+	/// Subscribe to the messages passing through the room
+	pub async fn watch_room(&self, id: &RoomID) -> Option<broadcast::Receiver<WatchEvent>> {
+		let room = self.get_room(id)?;
+		let mut lock = room.lock().await;
+		Some(lock.clients.watch.subscribe())
+	}
+
+	/// Tick every room and check the connections of their clients
+	pub async fn tick(&self) {
+		let heartbeat = &self.heartbeat;
+		let futures = self
+			.rooms
+			.entries()
+			.into_iter()
+			.map(|(_, entry)| async move {
+				let mut lock = entry.room.lock().await;
+				lock.heartbeat(heartbeat).await;
+				lock.tick().await;
+				lock.publish();
+			});
+
+		futures::future::join_all(futures).await;
+	}
+
+	/// Send the message to every client in every room
+	async fn send_to_everyone(&self, msg: SocketMessage<E>) {
+		let futures = self.rooms.entries().into_iter().map(|(_, entry)| {
+			let msg = msg.clone();
+			async move {
+				let mut lock = entry.room.lock().await;
+				lock.clients.send_to_all(msg).await;
 			}
+		});
+
+		futures::future::join_all(futures).await;
+	}
+
+	/// Send a maintenance notice to every client in every room
+	pub async fn announce_maintenance(&self, notice: &str) {
+		self.send_to_everyone(SocketMessage::Maintenance(notice.to_string()))
+			.await;
+	}
+
+	/// Show a message of the admins to every client in every room
+	pub async fn broadcast(&self, notice: &str) {
+		self.send_to_everyone(SocketMessage::Notice(notice.to_string()))
+			.await;
+	}
+
+	/// Close every room and the sockets of their clients
+	pub async fn close_all(&self) {
+		let futures = self.rooms.drain().into_iter().map(|(_, entry)| async move {
+			entry.room.lock().await.cleanup().await;
+		});
+
+		futures::future::join_all(futures).await;
+	}
+}
+
+impl<S, E, G> RoomManager<S, E, G>
+where
+	S: Clone + Send + Serialize + DeserializeOwned,
+	E: Clone + Serialize + DeserializeOwned + Send,
+	G: ServerRoom<E> + TryFrom<S> + Send + Serialize + DeserializeOwned,
+{
+	/// Write a snapshot of all rooms to the given file
+	pub async fn save_to_file(&self, path: &str) -> std::io::Result<()> {
+		let mut rooms = serde_json::Map::new();
+		for (id, entry) in self.rooms.entries() {
+			let lock = entry.room.lock().await;
+			let value = serde_json::to_value(lock.snapshot())?;
+			rooms.insert(id.clone(), value);
 		}
-		v
+
+		let data = serde_json::to_string(&rooms)?;
+		tokio::fs::write(path, data).await
 	}
 
-	pub fn get_room(&self, id: &RoomID) -> Option<RoomRef<S, E, G>> {
-		match self.rooms.get(id) {
-			Some(c) => Some(c.clone()),
-			None => None,
+	/// Restore the rooms from a snapshot file, rooms with an already used id are skipped.
+	/// Returns the number of restored rooms.
+	pub async fn load_from_file(&self, path: &str) -> std::io::Result<usize> {
+		let data = tokio::fs::read_to_string(path).await?;
+		let rooms: HashMap<RoomID, RoomSnapshot<S, G>> = serde_json::from_str(&data)?;
+
+		let mut num_restored = 0;
+		for (id, snapshot) in rooms {
+			let room = Room::<S, E, G>::restore(id.clone(), snapshot);
+			if self.insert(room).is_none() {
+				warn!(room = id; "Room already exists, skip restoring it");
+				continue;
+			}
+			num_restored += 1;
 		}
+
+		info!("Restored {} rooms from {}", num_restored, path);
+		Ok(num_restored)
 	}
 
-	pub async fn process_request(&mut self, req: ServerRequest) -> ServerAnswer {
+	pub async fn process_request(&self, req: ServerRequest) -> ServerAnswer {
 		match req {
-			ServerRequest::CloseRoom(room_id) => match self.rooms.remove(&room_id) {
-				Some(room) => {
-					let mut lock = room.lock().await;
+			ServerRequest::CloseRoom(room_id) => match self.remove(&room_id) {
+				Some(entry) => {
+					let mut lock = entry.room.lock().await;
 					lock.cleanup().await;
 					ServerAnswer::Successful
 				}
@@ -580,15 +1755,53 @@ where
 				ServerAnswer::Successful
 			}
 			ServerRequest::ListRooms => {
-				let futures = self.rooms.iter().map(|(id, room)| async move {
-					let lock = room.lock().await;
-					RoomIndex::new(id.clone(), lock.deref())
-				});
-
-				let results = futures::future::join_all(futures).await;
-				ServerAnswer::RoomList(results)
+				let rooms = self
+					.rooms
+					.entries()
+					.into_iter()
+					.map(|(_, entry)| entry.listing.read().unwrap().index.clone())
+					.collect();
+				ServerAnswer::RoomList(rooms)
+			}
+			ServerRequest::SaveToFile(path) => match self.save_to_file(&path).await {
+				Ok(_) => ServerAnswer::Successful,
+				Err(e) => {
+					error!("Could not save rooms to {}: {}", path, e);
+					ServerAnswer::Unsuccessful
+				}
+			},
+			ServerRequest::LoadFromFile(path) => match self.load_from_file(&path).await {
+				Ok(_) => ServerAnswer::Successful,
+				Err(e) => {
+					error!("Could not load rooms from {}: {}", path, e);
+					ServerAnswer::Unsuccessful
+				}
+			},
+			ServerRequest::InspectRoom(room_id) => match self.get_room(&room_id) {
+				Some(room) => ServerAnswer::Details(room.lock().await.details().await),
+				None => ServerAnswer::Unsuccessful,
+			},
+			ServerRequest::Kick(room_id, client_id) => {
+				let Some(room) = self.get_room(&room_id) else {
+					return ServerAnswer::Unsuccessful;
+				};
+
+				let mut lock = room.lock().await;
+				if lock.clients.is_spectator(client_id) {
+					lock.unregister(client_id).await;
+				} else if lock.clients.get(&client_id).is_some() {
+					lock.kick(client_id).await;
+				} else {
+					return ServerAnswer::Unsuccessful;
+				}
+				ServerAnswer::Successful
+			}
+			ServerRequest::Broadcast(notice) => {
+				self.broadcast(&notice).await;
+				ServerAnswer::Successful
 			}
-			_ => todo!(),
+			// Streams are handled by the admin connection itself
+			ServerRequest::Watch(_) => ServerAnswer::Error(String::from("Can't watch a room here")),
 		}
 	}
 }
@@ -0,0 +1,65 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+/// Salted hash of a room password, the password itself is never stored
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PasswordHash {
+	salt: String,
+	hash: String,
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+	bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn hash(salt: &str, password: &str) -> String {
+	let mut hasher = Sha256::new();
+	hasher.update(salt.as_bytes());
+	hasher.update(password.as_bytes());
+	to_hex(&hasher.finalize())
+}
+
+impl PasswordHash {
+	pub fn new(password: &str) -> Self {
+		let salt = to_hex(&rand::thread_rng().gen::<[u8; 16]>());
+		let hash = hash(&salt, password);
+		Self { salt, hash }
+	}
+
+	/// Whether the password matches, takes the same time for every wrong password
+	pub fn verify(&self, password: &str) -> bool {
+		let hash = hash(&self.salt, password);
+		hash.as_bytes().ct_eq(self.hash.as_bytes()).into()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn verifies_only_the_password() {
+		let stored = PasswordHash::new("secret");
+		assert!(stored.verify("secret"));
+		assert!(!stored.verify("Secret"));
+		assert!(!stored.verify(""));
+	}
+
+	#[test]
+	fn salts_differ() {
+		let a = PasswordHash::new("secret");
+		let b = PasswordHash::new("secret");
+		assert_ne!(a.hash, b.hash);
+	}
+
+	#[test]
+	fn never_serializes_the_password() {
+		let json = serde_json::to_string(&PasswordHash::new("secret")).unwrap();
+		assert!(!json.contains("secret"));
+
+		let restored: PasswordHash = serde_json::from_str(&json).unwrap();
+		assert!(restored.verify("secret"));
+	}
+}
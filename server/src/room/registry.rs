@@ -0,0 +1,83 @@
+use std::collections::hash_map::{DefaultHasher, Entry};
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::RwLock;
+
+use super::RoomID;
+
+/// Number of independently locked parts of the registry
+pub const NUM_SHARDS: usize = 16;
+
+/// Map from room ids to rooms, which can be shared without a global lock.
+///
+/// The ids are spread over several shards, each behind its own lock.
+/// The locks are only held to look up, insert or remove an entry,
+/// never while waiting on a room.
+pub struct Registry<T> {
+	shards: Vec<RwLock<HashMap<RoomID, T>>>,
+}
+
+impl<T: Clone> Default for Registry<T> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<T: Clone> Registry<T> {
+	pub fn new() -> Self {
+		Self {
+			shards: (0..NUM_SHARDS).map(|_| RwLock::default()).collect(),
+		}
+	}
+
+	fn shard(&self, id: &RoomID) -> &RwLock<HashMap<RoomID, T>> {
+		let mut hasher = DefaultHasher::new();
+		id.hash(&mut hasher);
+		&self.shards[hasher.finish() as usize % self.shards.len()]
+	}
+
+	pub fn get(&self, id: &RoomID) -> Option<T> {
+		self.shard(id).read().unwrap().get(id).cloned()
+	}
+
+	pub fn contains(&self, id: &RoomID) -> bool {
+		self.shard(id).read().unwrap().contains_key(id)
+	}
+
+	/// Insert the value, if the id isn't used yet. Returns whether it was inserted.
+	pub fn try_insert(&self, id: RoomID, value: T) -> bool {
+		match self.shard(&id).write().unwrap().entry(id) {
+			Entry::Occupied(_) => false,
+			Entry::Vacant(entry) => {
+				entry.insert(value);
+				true
+			}
+		}
+	}
+
+	pub fn remove(&self, id: &RoomID) -> Option<T> {
+		self.shard(id).write().unwrap().remove(id)
+	}
+
+	/// A copy of every entry, the registry may change while they are used
+	pub fn entries(&self) -> Vec<(RoomID, T)> {
+		self.shards
+			.iter()
+			.flat_map(|shard| {
+				let shard = shard.read().unwrap();
+				shard
+					.iter()
+					.map(|(id, value)| (id.clone(), value.clone()))
+					.collect::<Vec<_>>()
+			})
+			.collect()
+	}
+
+	/// Remove every entry and return them
+	pub fn drain(&self) -> Vec<(RoomID, T)> {
+		self.shards
+			.iter()
+			.flat_map(|shard| shard.write().unwrap().drain().collect::<Vec<_>>())
+			.collect()
+	}
+}
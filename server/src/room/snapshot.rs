@@ -0,0 +1,147 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use tokio::time::Instant;
+
+use super::{client::ClientHandler, *};
+
+/// A seat of a room, which its player can reclaim with the session token after a restore
+#[derive(Serialize, Deserialize)]
+pub struct SeatSnapshot {
+	pub session: SessionToken,
+	pub name: String,
+	pub player_id: usize,
+	pub vote: Option<usize>,
+	/// Seats of bots are taken by a new bot after a restore
+	pub bot: bool,
+}
+
+/// Everything needed to restore a room after a restart
+#[derive(Serialize, Deserialize)]
+pub struct RoomSnapshot<S, G> {
+	pub setting: RoomSetting<S>,
+	pub game: G,
+	pub state: RoomState,
+	pub num_players: usize,
+
+	pub vote: Option<VotingType>,
+	pub seats: Vec<SeatSnapshot>,
+
+	/// When the room was created, in seconds since the unix epoch
+	pub created: i64,
+}
+
+impl<S, E, G> Room<S, E, G>
+where
+	S: Clone,
+	E: Clone + Serialize + DeserializeOwned,
+	G: ServerRoom<E> + Send + TryFrom<S>,
+{
+	/// Take a snapshot of the room, borrowing the setting and the game
+	pub fn snapshot(&self) -> RoomSnapshot<&S, &G> {
+		let connected = self.clients.values().map(|client| SeatSnapshot {
+			session: client.session.clone(),
+			name: client.data.name.clone(),
+			player_id: client.player_id,
+			vote: client.vote,
+			bot: client.is_bot(),
+		});
+
+		let reserved =
+			self.reservations
+				.iter()
+				.filter(|(_, r)| r.is_valid())
+				.map(|(session, r)| SeatSnapshot {
+					session: session.clone(),
+					name: r.data.name.clone(),
+					player_id: r.player_id,
+					vote: r.vote,
+					bot: false,
+				});
+
+		RoomSnapshot {
+			setting: RoomSetting {
+				public: self.setting.public,
+				password: self.setting.password.clone(),
+				game_setting: &self.setting.game_setting,
+			},
+			game: &self.game,
+			state: self.state.clone(),
+			num_players: self.num_players,
+
+			vote: self.vote.clone(),
+			seats: connected.chain(reserved).collect(),
+
+			created: self.created,
+		}
+	}
+
+	/// Restore a room, every seat is reserved for its previous player and bots sit down again
+	pub fn restore(id: RoomID, snapshot: RoomSnapshot<S, G>) -> Self {
+		let expires = Instant::now() + SEAT_RESERVATION;
+
+		let (bot_seats, seats): (Vec<_>, Vec<_>) =
+			snapshot.seats.into_iter().partition(|seat| seat.bot);
+
+		let reservations = seats
+			.into_iter()
+			.map(|seat| {
+				let reservation = Reservation {
+					data: ClientData {
+						name: seat.name,
+						session: None,
+						secret: None,
+						version: None,
+						spectate: false,
+						bot: false,
+						address: None,
+					},
+					player_id: seat.player_id,
+					vote: seat.vote,
+					expires,
+				};
+				(seat.session, reservation)
+			})
+			.collect();
+
+		// Nobody is connected anymore to finish choosing the teams
+		let (state, vote) = match snapshot.state {
+			RoomState::Teaming => (RoomState::Entering, None),
+			state => (state, snapshot.vote),
+		};
+
+		let mut room = Room {
+			_marker: PhantomData,
+			id,
+			clients: ClientHandler::default(),
+
+			setting: snapshot.setting,
+			game: snapshot.game,
+			state,
+			num_players: snapshot.num_players,
+
+			num_votes: 0,
+			vote,
+
+			reservations,
+			bans: Vec::new(),
+			bots: HashMap::new(),
+
+			last_heartbeat: Instant::now(),
+
+			mates: HashMap::new(),
+
+			deadline: None,
+
+			listing: ListingRef::default(),
+			closed: false,
+			created: snapshot.created,
+			lobby: None,
+		};
+
+		for seat in bot_seats {
+			room.register_bot(seat.player_id, seat.session);
+		}
+		room
+	}
+}
@@ -0,0 +1,201 @@
+use rand::prelude::SliceRandom;
+use std::collections::HashMap;
+
+/// Assign every client a seat (player id).
+///
+/// `mates` maps a client to the client it wants to partner with and
+/// `teams` contains the seats of each team.
+/// Two clients who requested each other are seated in the same team, if there is room.
+/// Everyone else is seated randomly on the remaining seats.
+pub fn resolve_seating(
+	clients: &[usize],
+	mates: &HashMap<usize, usize>,
+	teams: &[Vec<usize>],
+) -> HashMap<usize, usize> {
+	let mut rng = rand::thread_rng();
+
+	let is_mutual = |a: usize, b: usize| mates.get(&a) == Some(&b) && mates.get(&b) == Some(&a);
+
+	let mut pairs = vec![];
+	let mut singles = vec![];
+	for &id in clients {
+		match mates.get(&id) {
+			Some(&mate) if mate != id && clients.contains(&mate) && is_mutual(id, mate) => {
+				if id < mate {
+					pairs.push((id, mate));
+				}
+			}
+			_ => singles.push(id),
+		}
+	}
+
+	let mut free: Vec<Vec<usize>> = teams.to_vec();
+	free.shuffle(&mut rng);
+	for seats in free.iter_mut() {
+		seats.shuffle(&mut rng);
+	}
+	pairs.shuffle(&mut rng);
+
+	let mut seating = HashMap::new();
+	for (a, b) in pairs {
+		match free.iter_mut().find(|seats| 2 <= seats.len()) {
+			Some(seats) => {
+				seating.insert(a, seats.pop().unwrap());
+				seating.insert(b, seats.pop().unwrap());
+			}
+			None => {
+				singles.push(a);
+				singles.push(b);
+			}
+		}
+	}
+
+	let mut rest: Vec<usize> = free.into_iter().flatten().collect();
+	rest.shuffle(&mut rng);
+	singles.shuffle(&mut rng);
+
+	for (id, seat) in std::iter::zip(singles, rest) {
+		seating.insert(id, seat);
+	}
+
+	seating
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Seats 0 and 2 against 1 and 3
+	fn two_teams() -> Vec<Vec<usize>> {
+		vec![vec![0, 2], vec![1, 3]]
+	}
+
+	fn team_of(teams: &[Vec<usize>], seat: usize) -> usize {
+		teams.iter().position(|team| team.contains(&seat)).unwrap()
+	}
+
+	fn same_team(
+		teams: &[Vec<usize>],
+		seating: &HashMap<usize, usize>,
+		a: usize,
+		b: usize,
+	) -> bool {
+		team_of(teams, seating[&a]) == team_of(teams, seating[&b])
+	}
+
+	/// Every client got its own seat
+	fn assert_seated(clients: &[usize], seating: &HashMap<usize, usize>) {
+		assert_eq!(seating.len(), clients.len());
+		let mut seats: Vec<usize> = seating.values().copied().collect();
+		seats.sort();
+		seats.dedup();
+		assert_eq!(seats.len(), clients.len());
+	}
+
+	// The seating is random, so every case is tried a few times
+	const RUNS: usize = 50;
+
+	#[test]
+	fn mutual_picks_share_a_team() {
+		let teams = two_teams();
+		let clients = [0, 1, 2, 3];
+		let mates = HashMap::from([(0, 1), (1, 0)]);
+
+		for _ in 0..RUNS {
+			let seating = resolve_seating(&clients, &mates, &teams);
+			assert_seated(&clients, &seating);
+			assert!(same_team(&teams, &seating, 0, 1));
+		}
+	}
+
+	#[test]
+	fn two_mutual_pairs_fill_both_teams() {
+		let teams = two_teams();
+		let clients = [5, 6, 7, 8];
+		let mates = HashMap::from([(5, 8), (8, 5), (6, 7), (7, 6)]);
+
+		for _ in 0..RUNS {
+			let seating = resolve_seating(&clients, &mates, &teams);
+			assert_seated(&clients, &seating);
+			assert!(same_team(&teams, &seating, 5, 8));
+			assert!(same_team(&teams, &seating, 6, 7));
+		}
+	}
+
+	#[test]
+	fn partial_picks_are_seated_randomly() {
+		let teams = two_teams();
+		let clients = [0, 1, 2, 3];
+		// 0 asks for 1, but 1 and 2 asked for each other
+		let mates = HashMap::from([(0, 1), (1, 2), (2, 1)]);
+
+		for _ in 0..RUNS {
+			let seating = resolve_seating(&clients, &mates, &teams);
+			assert_seated(&clients, &seating);
+			assert!(same_team(&teams, &seating, 1, 2));
+			// The leftovers end up in the other team
+			assert!(same_team(&teams, &seating, 0, 3));
+		}
+	}
+
+	#[test]
+	fn one_sided_picks_are_ignored() {
+		let teams = two_teams();
+		let clients = [0, 1, 2, 3];
+		let mates = HashMap::from([(0, 1), (2, 3)]);
+
+		let mut apart = false;
+		for _ in 0..RUNS {
+			let seating = resolve_seating(&clients, &mates, &teams);
+			assert_seated(&clients, &seating);
+			apart |= !same_team(&teams, &seating, 0, 1);
+		}
+		assert!(apart);
+	}
+
+	#[test]
+	fn picking_yourself_or_absent_clients_is_ignored() {
+		let teams = two_teams();
+		let clients = [0, 1, 2, 3];
+		let mates = HashMap::from([(0, 0), (1, 9), (9, 1)]);
+
+		for _ in 0..RUNS {
+			let seating = resolve_seating(&clients, &mates, &teams);
+			assert_seated(&clients, &seating);
+		}
+	}
+
+	#[test]
+	fn pairs_without_room_become_leftovers() {
+		// Two teams of three, only one pair fits into each
+		let teams = vec![vec![0, 2, 4], vec![1, 3, 5]];
+		let clients = [0, 1, 2, 3, 4, 5];
+		let mates = HashMap::from([(0, 1), (1, 0), (2, 3), (3, 2), (4, 5), (5, 4)]);
+
+		for _ in 0..RUNS {
+			let seating = resolve_seating(&clients, &mates, &teams);
+			assert_seated(&clients, &seating);
+
+			let together = [(0, 1), (2, 3), (4, 5)]
+				.iter()
+				.filter(|(a, b)| same_team(&teams, &seating, *a, *b))
+				.count();
+			assert_eq!(together, 2);
+		}
+	}
+
+	#[test]
+	fn seats_of_unused_teams_stay_free() {
+		// An early started room with three players
+		let teams = vec![vec![0, 2], vec![1]];
+		let clients = [3, 4, 5];
+		let mates = HashMap::from([(3, 5), (5, 3)]);
+
+		for _ in 0..RUNS {
+			let seating = resolve_seating(&clients, &mates, &teams);
+			assert_seated(&clients, &seating);
+			assert!(same_team(&teams, &seating, 3, 5));
+			assert_eq!(seating[&4], 1);
+		}
+	}
+}
@@ -0,0 +1,683 @@
+use async_trait::async_trait;
+use axum::extract::ws::Message;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::UnboundedReceiver;
+
+use super::bot::Bot;
+use super::*;
+use crate::socket_message::{ClientData, SocketMessage::*, VotingType};
+use crate::transport::ChannelTransport;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct TestSetting {
+	pub min_players: usize,
+	pub max_players: usize,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct TestEvent;
+
+/// Game of two teams, seats 0 and 2 against 1 and 3
+#[derive(Serialize, Deserialize)]
+pub(crate) struct TestGame {
+	setting: TestSetting,
+	/// The number of players of every started game
+	starts: Vec<usize>,
+	turn: Option<usize>,
+	/// The players, for whom the deadline expired
+	timeouts: Vec<usize>,
+	/// The players, who sent an event
+	events: Vec<usize>,
+}
+
+impl TryFrom<TestSetting> for TestGame {
+	type Error = ();
+
+	fn try_from(setting: TestSetting) -> Result<Self, Self::Error> {
+		Ok(Self {
+			setting,
+			starts: vec![],
+			turn: None,
+			timeouts: vec![],
+			events: vec![],
+		})
+	}
+}
+
+#[async_trait]
+impl ServerRoom<TestEvent> for TestGame {
+	const GAME_VERSION: u32 = 1;
+	type Err = ();
+
+	async fn start(&mut self, _clients: &mut ClientHandler, num_players: usize) -> Result<(), ()> {
+		self.starts.push(num_players);
+		Ok(())
+	}
+
+	async fn on_enter(&mut self, _clients: &mut ClientHandler, _plr_id: usize) {}
+	async fn on_leave(&mut self, _clients: &mut ClientHandler, _plr_id: usize) {}
+	async fn on_spectate(&mut self, _clients: &mut ClientHandler, _client_id: usize) {}
+	async fn on_event(
+		&mut self,
+		_clients: &mut ClientHandler,
+		_event: TestEvent,
+		plr_id: usize,
+	) -> Result<(), ()> {
+		self.events.push(plr_id);
+		Ok(())
+	}
+
+	fn on_turn(&self) -> Option<usize> {
+		self.turn
+	}
+	fn ends_turn(&self, _event: &TestEvent) -> bool {
+		true
+	}
+	async fn on_timeout(&mut self, _clients: &mut ClientHandler, plr_id: usize) {
+		self.timeouts.push(plr_id);
+	}
+
+	fn new_bot(&self) -> Option<Box<dyn Bot<Self, TestEvent>>> {
+		Some(Box::new(TestBot))
+	}
+
+	fn summary(&self) -> String {
+		String::new()
+	}
+
+	fn get_player_bound(&self) -> (usize, usize) {
+		(self.setting.min_players, self.setting.max_players)
+	}
+	fn get_teams(&self) -> Vec<Vec<usize>> {
+		vec![vec![0, 2], vec![1, 3]]
+	}
+	fn should_end(&self) -> bool {
+		false
+	}
+}
+
+/// Plays whenever it is on turn
+struct TestBot;
+
+impl Bot<TestGame, TestEvent> for TestBot {
+	fn act(&mut self, game: &TestGame, plr_id: usize) -> Option<TestEvent> {
+		(game.turn == Some(plr_id)).then_some(TestEvent)
+	}
+}
+
+type TestRoom = Room<TestSetting, TestEvent, TestGame>;
+
+fn new_room(min_players: usize, max_players: usize) -> TestRoom {
+	let setting = RoomSetting {
+		public: true,
+		password: None,
+		game_setting: TestSetting {
+			min_players,
+			max_players,
+		},
+	};
+	Room::try_new(RoomID::from("TEST"), setting).unwrap()
+}
+
+fn client_data(name: &str) -> ClientData {
+	ClientData {
+		name: String::from(name),
+		session: None,
+		secret: None,
+		version: None,
+		spectate: false,
+		bot: false,
+		address: None,
+	}
+}
+
+/// Register a client over a channel, returns its client id and what it receives
+async fn join(room: &mut TestRoom, name: &str) -> (usize, UnboundedReceiver<Message>) {
+	join_as(room, client_data(name)).await
+}
+
+async fn join_as(room: &mut TestRoom, data: ClientData) -> (usize, UnboundedReceiver<Message>) {
+	let (transport, inbox) = ChannelTransport::new();
+	let (_, id) = room.register(data, Box::new(transport)).await;
+	(id, inbox)
+}
+
+fn from_address(name: &str, address: &str) -> ClientData {
+	ClientData {
+		address: Some(address.parse().unwrap()),
+		..client_data(name)
+	}
+}
+
+async fn vote_all(room: &mut TestRoom, ids: &[usize], vote: usize) {
+	for &id in ids {
+		room.handle_input(Vote(vote, id), id).await;
+	}
+}
+
+#[tokio::test]
+async fn start_early_then_team_then_play() {
+	let mut room = new_room(2, 4);
+
+	let mut ids = vec![];
+	for name in ["Alice", "Bob", "Carol"] {
+		ids.push(join(&mut room, name).await.0);
+	}
+	assert_eq!(room.vote, Some(VotingType::StartGame));
+
+	vote_all(&mut room, &ids, 0).await;
+	assert_eq!(room.state, RoomState::Teaming);
+	assert_eq!(room.vote, Some(VotingType::Teaming));
+	assert_eq!(room.num_players, 3);
+
+	vote_all(&mut room, &ids, 0).await;
+	assert_eq!(room.state, RoomState::Playing);
+	assert_eq!(room.game.starts, vec![3]);
+}
+
+#[tokio::test]
+async fn start_vote_needs_a_majority() {
+	let mut room = new_room(2, 4);
+
+	let (alice, _) = join(&mut room, "Alice").await;
+	let (bob, _) = join(&mut room, "Bob").await;
+
+	room.handle_input(Vote(0, alice), alice).await;
+	room.handle_input(Vote(1, bob), bob).await;
+	assert_eq!(room.state, RoomState::Entering);
+	assert_eq!(room.vote, None);
+}
+
+#[tokio::test]
+async fn start_vote_ends_when_too_few_stay() {
+	let mut room = new_room(2, 4);
+
+	let (alice, _) = join(&mut room, "Alice").await;
+	let (bob, _) = join(&mut room, "Bob").await;
+	room.handle_input(Vote(0, alice), alice).await;
+
+	room.unregister(bob).await;
+	assert_eq!(room.vote, None);
+	assert_eq!(room.state, RoomState::Entering);
+}
+
+#[tokio::test]
+async fn leaving_while_teaming_reopens_every_seat() {
+	let mut room = new_room(2, 4);
+
+	let mut ids = vec![];
+	for name in ["Alice", "Bob", "Carol"] {
+		ids.push(join(&mut room, name).await.0);
+	}
+	vote_all(&mut room, &ids, 0).await;
+	assert_eq!(room.state, RoomState::Teaming);
+
+	room.unregister(ids[2]).await;
+	assert_eq!(room.state, RoomState::Entering);
+	assert_eq!(room.num_players, 4);
+	assert_eq!(room.free_seats().len(), 2);
+}
+
+#[tokio::test]
+async fn full_room_starts_without_vote() {
+	let mut room = new_room(4, 4);
+
+	let mut ids = vec![];
+	for name in ["Alice", "Bob", "Carol", "Dave"] {
+		ids.push(join(&mut room, name).await.0);
+		if ids.len() < 4 {
+			assert_eq!(room.vote, None);
+		}
+	}
+	assert_eq!(room.vote, Some(VotingType::Teaming));
+
+	vote_all(&mut room, &ids, 0).await;
+	assert_eq!(room.game.starts, vec![4]);
+}
+
+#[tokio::test]
+async fn kick_vote_removes_and_bans() {
+	let mut room = new_room(4, 4);
+
+	let (alice, _) = join(&mut room, "Alice").await;
+	let (bob, _) = join(&mut room, "Bob").await;
+	let (carol, _) = join_as(&mut room, from_address("Carol", "10.0.0.3")).await;
+	let session = room.clients[&carol].session.clone();
+
+	room.handle_input(NewVote(VotingType::Kick(carol)), alice)
+		.await;
+	assert_eq!(room.vote, Some(VotingType::Kick(carol)));
+
+	// The target has no say in it
+	room.handle_input(Vote(1, carol), carol).await;
+	assert!(room.clients.contains_key(&carol));
+
+	room.handle_input(Vote(0, bob), bob).await;
+	assert!(!room.clients.contains_key(&carol));
+	assert_eq!(room.vote, None);
+
+	let rejoin = ClientData {
+		session: Some(session),
+		..client_data("Carol")
+	};
+	assert_eq!(room.admit(&rejoin).await, Err(CloseReason::Banned));
+	// Dropping the session doesn't help
+	let rejoin = from_address("Carol", "10.0.0.3");
+	assert_eq!(room.admit(&rejoin).await, Err(CloseReason::Banned));
+
+	let other = from_address("Dave", "10.0.0.4");
+	assert_eq!(room.admit(&other).await, Ok(()));
+}
+
+#[tokio::test]
+async fn kick_vote_needs_a_majority() {
+	let mut room = new_room(4, 4);
+
+	let (alice, _) = join(&mut room, "Alice").await;
+	let (bob, _) = join(&mut room, "Bob").await;
+	let (carol, _) = join(&mut room, "Carol").await;
+
+	room.handle_input(NewVote(VotingType::Kick(carol)), alice)
+		.await;
+	room.handle_input(Vote(1, bob), bob).await;
+	assert!(room.clients.contains_key(&carol));
+	assert_eq!(room.vote, None);
+	assert!(room.bans.is_empty());
+}
+
+#[tokio::test]
+async fn kick_proposals_are_checked() {
+	let mut room = new_room(4, 4);
+
+	let (alice, _) = join(&mut room, "Alice").await;
+	let (bob, _) = join(&mut room, "Bob").await;
+	join(&mut room, "Carol").await;
+
+	// Nobody can kick themselves or someone, who isn't there
+	room.handle_input(NewVote(VotingType::Kick(alice)), alice)
+		.await;
+	room.handle_input(NewVote(VotingType::Kick(42)), alice)
+		.await;
+	assert_eq!(room.vote, None);
+
+	// Only one vote at a time
+	room.handle_input(NewVote(VotingType::Kick(bob)), alice)
+		.await;
+	room.handle_input(NewVote(VotingType::Kick(alice)), bob)
+		.await;
+	assert_eq!(room.vote, Some(VotingType::Kick(bob)));
+}
+
+/// A room of four playing clients, returns their ids and inboxes
+async fn playing_room() -> (TestRoom, Vec<(usize, UnboundedReceiver<Message>)>) {
+	let mut room = new_room(4, 4);
+
+	let mut clients = vec![];
+	for name in ["Alice", "Bob", "Carol", "Dave"] {
+		clients.push(join(&mut room, name).await);
+	}
+	let ids: Vec<usize> = clients.iter().map(|(id, _)| *id).collect();
+	vote_all(&mut room, &ids, 0).await;
+	assert_eq!(room.state, RoomState::Playing);
+
+	(room, clients)
+}
+
+fn with_session(name: &str, session: &SessionToken) -> ClientData {
+	ClientData {
+		session: Some(session.clone()),
+		..client_data(name)
+	}
+}
+
+/// The messages a client received since the last call
+async fn messages(inbox: &mut UnboundedReceiver<Message>) -> Vec<SocketMessage<TestEvent>> {
+	// The writer task of the client has to catch up first
+	tokio::task::yield_now().await;
+
+	let mut messages = vec![];
+	while let Ok(Message::Text(text)) = inbox.try_recv() {
+		messages.push(serde_json::from_str(text.as_str()).unwrap());
+	}
+	messages
+}
+
+/// The seat and the session token the client was told about
+async fn player_id(inbox: &mut UnboundedReceiver<Message>) -> Option<(usize, SessionToken)> {
+	messages(inbox)
+		.await
+		.into_iter()
+		.rev()
+		.find_map(|msg| match msg {
+			PlayerID(_, plr_id, _, session) => Some((plr_id, session)),
+			_ => None,
+		})
+}
+
+#[tokio::test]
+async fn disconnected_players_reclaim_their_seat() {
+	let (mut room, mut clients) = playing_room().await;
+	let (dave, inbox) = &mut clients[3];
+	// The seats were assigned again by teaming, after the player id was sent
+	let (_, session) = player_id(inbox).await.unwrap();
+	let plr_id = room.clients[dave].player_id;
+	assert_eq!(room.clients[dave].session, session);
+
+	room.unregister(*dave).await;
+	let reservation = &room.reservations[&session];
+	assert_eq!(reservation.player_id, plr_id);
+	let kept = reservation.expires - Instant::now();
+	assert!(SEAT_RESERVATION - Duration::from_secs(1) < kept && kept <= SEAT_RESERVATION);
+
+	// Nobody else gets the seat
+	assert!(room.free_seats().is_empty());
+	assert_eq!(room.admit(&with_session("Dave", &session)).await, Ok(()));
+
+	let (dave, mut inbox) = join_as(&mut room, with_session("Dave", &session)).await;
+	assert_eq!(room.clients[&dave].player_id, plr_id);
+	assert_eq!(player_id(&mut inbox).await, Some((plr_id, session)));
+	assert!(room.reservations.is_empty());
+}
+
+#[tokio::test]
+async fn reconnects_replace_stale_clients() {
+	let (mut room, mut clients) = playing_room().await;
+	let (dave, inbox) = &mut clients[3];
+	let (_, session) = player_id(inbox).await.unwrap();
+	let plr_id = room.clients[dave].player_id;
+
+	// The old connection didn't notice the disconnect yet
+	let (new_dave, _) = join_as(&mut room, with_session("Dave", &session)).await;
+	assert!(!room.clients.contains_key(dave));
+	assert_eq!(room.clients[&new_dave].player_id, plr_id);
+	assert_eq!(room.clients.len(), 4);
+}
+
+#[tokio::test]
+async fn seats_are_lost_after_the_reservation() {
+	let (mut room, mut clients) = playing_room().await;
+	let (dave, inbox) = &mut clients[3];
+	let (_, session) = player_id(inbox).await.unwrap();
+	let plr_id = room.clients[dave].player_id;
+
+	room.unregister(*dave).await;
+	room.reservations.get_mut(&session).unwrap().expires = Instant::now();
+
+	let (erin, _) = join(&mut room, "Erin").await;
+	assert_eq!(room.clients[&erin].player_id, plr_id);
+
+	// Dave may only watch now
+	let (dave, _) = join_as(&mut room, with_session("Dave", &session)).await;
+	assert!(room.clients.spectators.contains_key(&dave));
+	assert!(room.reservations.is_empty());
+}
+
+#[tokio::test]
+async fn players_reclaim_their_seat_from_a_bot() {
+	let (mut room, mut clients) = playing_room().await;
+	let (dave, inbox) = &mut clients[3];
+	let (_, session) = player_id(inbox).await.unwrap();
+	let plr_id = room.clients[dave].player_id;
+
+	// A bot takes over, once the reservation expired
+	room.unregister(*dave).await;
+	room.reservations.get_mut(&session).unwrap().expires = Instant::now();
+	room.tick().await;
+
+	let bot = *room.bots.keys().next().unwrap();
+	assert_eq!(room.clients[&bot].player_id, plr_id);
+	assert!(room.reservations.is_empty());
+
+	let (dave, _) = join_as(&mut room, with_session("Dave", &session)).await;
+	assert!(!room.clients.contains_key(&bot));
+	assert!(room.bots.is_empty());
+	assert_eq!(room.clients[&dave].player_id, plr_id);
+	assert!(!room.clients[&dave].is_bot());
+	assert!(room.reservations.is_empty());
+}
+
+#[tokio::test]
+async fn spectators_only_watch() {
+	let (mut room, clients) = playing_room().await;
+	let alice = clients[0].0;
+
+	let data = ClientData {
+		spectate: true,
+		..client_data("Eve")
+	};
+	let (eve, mut inbox) = join_as(&mut room, data).await;
+	assert!(room.clients.is_spectator(eve));
+	assert!(messages(&mut inbox)
+		.await
+		.iter()
+		.any(|msg| matches!(msg, SpectatorID(id, 4) if *id == eve)));
+
+	// The cards of the players are only sent to them
+	for plr_id in 0..4 {
+		room.clients.ev_send_to(plr_id, TestEvent).await;
+	}
+	assert!(!messages(&mut inbox)
+		.await
+		.iter()
+		.any(|msg| matches!(msg, Event(_))));
+
+	// Spectators can't play or vote
+	room.handle_input(Event(TestEvent), eve).await;
+	assert!(room.game.events.is_empty());
+
+	room.handle_input(NewVote(VotingType::Kick(alice)), eve)
+		.await;
+	assert_eq!(room.vote, None);
+
+	room.handle_input(NewVote(VotingType::Kick(alice)), clients[1].0)
+		.await;
+	assert_eq!(room.vote, Some(VotingType::Kick(alice)));
+	room.handle_input(Vote(0, eve), eve).await;
+	assert_eq!(room.num_votes, 1);
+}
+
+#[tokio::test]
+async fn kicked_players_lose_their_seat() {
+	let mut room = new_room(4, 4);
+
+	let mut ids = vec![];
+	for name in ["Alice", "Bob", "Carol", "Dave"] {
+		ids.push(join(&mut room, name).await.0);
+	}
+	vote_all(&mut room, &ids, 0).await;
+	assert_eq!(room.state, RoomState::Playing);
+
+	let session = room.clients[&ids[3]].session.clone();
+	room.kick(ids[3]).await;
+
+	assert!(!room.reservations.contains_key(&session));
+	assert_eq!(room.free_seats().len(), 1);
+	let rejoin = ClientData {
+		session: Some(session),
+		..client_data("Dave")
+	};
+	assert_eq!(room.admit(&rejoin).await, Err(CloseReason::Banned));
+}
+
+#[tokio::test]
+async fn neighbours_of_kicked_clients_keep_their_seats() {
+	let mut room = new_room(4, 4);
+
+	let (alice, _) = join(&mut room, "Alice").await;
+	let (bob, _) = join(&mut room, "Bob").await;
+	let (dave, _) = join_as(&mut room, from_address("Dave", "10.0.0.4")).await;
+	let (erin, _) = join_as(&mut room, from_address("Erin", "10.0.0.4")).await;
+	vote_all(&mut room, &[alice, bob, dave, erin], 0).await;
+	assert_eq!(room.state, RoomState::Playing);
+
+	// Erin loses her connection, while Dave is kicked
+	let session = room.clients[&erin].session.clone();
+	room.unregister(erin).await;
+	room.kick(dave).await;
+
+	let rejoin = ClientData {
+		session: Some(session),
+		..from_address("Erin", "10.0.0.4")
+	};
+	assert_eq!(room.admit(&rejoin).await, Ok(()));
+	assert_eq!(
+		room.admit(&from_address("Frank", "10.0.0.4")).await,
+		Err(CloseReason::Banned)
+	);
+}
+
+#[tokio::test]
+async fn password_is_checked() {
+	let mut room = new_room(4, 4);
+	room.setting.password = Some(PasswordHash::new("secret"));
+
+	let guest = |secret: Option<&str>| ClientData {
+		secret: secret.map(String::from),
+		..client_data("Alice")
+	};
+	assert_eq!(
+		room.admit(&guest(None)).await,
+		Err(CloseReason::WrongPassword)
+	);
+	assert_eq!(
+		room.admit(&guest(Some("wrong"))).await,
+		Err(CloseReason::WrongPassword)
+	);
+	assert_eq!(room.admit(&guest(Some("secret"))).await, Ok(()));
+}
+
+#[tokio::test]
+async fn deadline_follows_the_turn() {
+	let mut room = new_room(4, 4);
+	room.game.turn = Some(1);
+
+	let mut ids = vec![];
+	for name in ["Alice", "Bob", "Carol", "Dave"] {
+		ids.push(join(&mut room, name).await.0);
+	}
+	vote_all(&mut room, &ids, 0).await;
+	assert_eq!(room.deadline.map(|d| d.plr_id), Some(1));
+
+	// The game acts for the player and they get another turn
+	room.deadline.as_mut().unwrap().expires = Instant::now();
+	room.tick().await;
+	assert_eq!(room.game.timeouts, vec![1]);
+	assert!(TURN_TIME.as_secs() - 1 <= room.deadline.unwrap().remaining_secs());
+
+	room.game.turn = Some(2);
+	room.handle_input(Event(TestEvent), ids[0]).await;
+	assert_eq!(room.deadline.map(|d| d.plr_id), Some(2));
+
+	// Deadlines aren't part of snapshots
+	room.deadline = None;
+	room.tick().await;
+	assert_eq!(room.deadline.map(|d| d.plr_id), Some(2));
+}
+
+/// A room, where Alice plays with a bot
+async fn room_with_bot() -> (TestRoom, usize, usize) {
+	let mut room = new_room(2, 4);
+	let (alice, _) = join(&mut room, "Alice").await;
+	room.handle_input(AddBot, alice).await;
+	let bot = *room.bots.keys().next().unwrap();
+
+	vote_all(&mut room, &[alice], 0).await;
+	vote_all(&mut room, &[alice], 0).await;
+	assert_eq!(room.state, RoomState::Playing);
+	(room, alice, bot)
+}
+
+#[tokio::test]
+async fn bots_act_on_their_turn() {
+	let (mut room, alice, bot) = room_with_bot().await;
+	let plr_id = room.clients[&bot].player_id;
+
+	room.game.turn = Some(room.clients[&alice].player_id);
+	room.tick().await;
+	assert!(room.game.events.is_empty());
+
+	room.game.turn = Some(plr_id);
+	room.tick().await;
+	assert_eq!(room.game.events, vec![plr_id]);
+}
+
+#[tokio::test]
+async fn clients_cannot_claim_to_be_bots() {
+	let mut room = new_room(2, 4);
+	let data: ClientData = serde_json::from_str(r#"{"name": "Mallory", "bot": true}"#).unwrap();
+	let (id, _) = join_as(&mut room, data).await;
+	assert!(!room.clients[&id].is_bot());
+}
+
+#[tokio::test]
+async fn bot_seats_are_restored_as_bots() {
+	let (room, alice, bot) = room_with_bot().await;
+	let session = room.clients[&alice].session.clone();
+	let plr_id = room.clients[&bot].player_id;
+
+	let json = serde_json::to_string(&room.snapshot()).unwrap();
+	let mut room = TestRoom::restore(RoomID::from("TEST"), serde_json::from_str(&json).unwrap());
+
+	let rejoin = ClientData {
+		session: Some(session),
+		..client_data("Alice")
+	};
+	let (alice, _) = join_as(&mut room, rejoin).await;
+	assert!(!room.clients[&alice].is_bot());
+
+	let bots: Vec<usize> = room
+		.bots
+		.keys()
+		.map(|id| room.clients[id].player_id)
+		.collect();
+	assert_eq!(bots, vec![plr_id]);
+}
+
+#[tokio::test]
+async fn filter_counts_humans_only() {
+	let (room, _, _) = room_with_bot().await;
+	let index = RoomIndex::new(room.id.clone(), &room);
+	assert_eq!((index.players.len(), index.bots), (2, 1));
+
+	let filter = |min_players, max_players| RoomFilter {
+		min_players,
+		max_players,
+		..Default::default()
+	};
+	assert!(filter(Some(1), Some(1)).matches(&index));
+	assert!(!filter(Some(2), None).matches(&index));
+	assert!(!filter(None, Some(0)).matches(&index));
+}
+
+#[test]
+fn filter_checks_the_seats() {
+	let index = RoomIndex {
+		max_players: 4,
+		free_seats: 0,
+		..Default::default()
+	};
+	let filter = |joinable, seats| RoomFilter {
+		joinable,
+		seats,
+		..Default::default()
+	};
+	assert!(filter(false, Some(4)).matches(&index));
+	assert!(!filter(false, Some(6)).matches(&index));
+	assert!(!filter(true, None).matches(&index));
+	assert!(filter(true, None).matches(&RoomIndex {
+		free_seats: 1,
+		..index
+	}));
+}
+
+#[test]
+fn index_tells_about_private_rooms_and_passwords() {
+	let mut room = new_room(4, 4);
+	let index = RoomIndex::new(room.id.clone(), &room);
+	assert!(index.public && !index.password);
+
+	room.setting.public = false;
+	room.setting.password = Some(PasswordHash::new("secret"));
+	let index = RoomIndex::new(room.id.clone(), &room);
+	assert!(!index.public && index.password);
+}
@@ -0,0 +1,130 @@
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+/// How many events a watcher may fall behind, before it misses some
+pub const WATCH_CAPACITY: usize = 1024;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum Direction {
+	/// Sent by a client (or bot) to the room
+	Incoming,
+	/// Sent by the room to one or more clients
+	Outgoing,
+	/// Sent by a client, but dropped before it reached the room, contains the reason
+	Rejected(String),
+}
+
+/// Who sent or received a message
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub enum Peer {
+	/// A single client and its seat, None for spectators
+	Client(usize, Option<usize>),
+	/// The client on the given seat
+	Player(usize),
+	Everyone,
+	EveryoneExceptClient(usize),
+	EveryoneExceptPlayer(usize),
+}
+
+/// A message passing through a room, as seen by the admin
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WatchEvent {
+	/// Milliseconds since the unix epoch
+	pub timestamp: i64,
+	pub direction: Direction,
+	pub peer: Peer,
+	/// The message as JSON, as it was sent over the socket, without session tokens
+	pub message: String,
+}
+
+/// Whoever knows the session token of a player can take over the seat, so watchers don't see it
+fn redact(message: String) -> String {
+	if !message.starts_with("{\"PlayerID\"") {
+		return message;
+	}
+
+	let Ok(mut value) = serde_json::from_str::<serde_json::Value>(&message) else {
+		return message;
+	};
+	match value
+		.get_mut("PlayerID")
+		.and_then(|fields| fields.get_mut(3))
+	{
+		Some(session) => *session = serde_json::Value::from("<redacted>"),
+		None => return message,
+	}
+	value.to_string()
+}
+
+/// The stream of messages of a room, only recorded while somebody watches
+#[derive(Default)]
+pub struct Watch {
+	sender: Option<broadcast::Sender<WatchEvent>>,
+}
+
+impl Watch {
+	pub fn subscribe(&mut self) -> broadcast::Receiver<WatchEvent> {
+		self.sender
+			.get_or_insert_with(|| broadcast::channel(WATCH_CAPACITY).0)
+			.subscribe()
+	}
+
+	/// Record the message, it is only serialized if somebody watches
+	pub fn record<F>(&self, direction: Direction, peer: Peer, message: F)
+	where
+		F: FnOnce() -> String,
+	{
+		let Some(sender) = &self.sender else {
+			return;
+		};
+		if sender.receiver_count() == 0 {
+			return;
+		}
+
+		let event = WatchEvent {
+			timestamp: chrono::Utc::now().timestamp_millis(),
+			direction,
+			peer,
+			message: redact(message()),
+		};
+		// Nobody may be listening anymore, that's fine
+		let _ = sender.send(event);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::socket_message::SocketMessage;
+
+	fn record(message: String) -> WatchEvent {
+		let mut watch = Watch::default();
+		let mut events = watch.subscribe();
+		watch.record(Direction::Outgoing, Peer::Player(0), || message);
+		events.try_recv().unwrap()
+	}
+
+	#[test]
+	fn session_tokens_are_redacted() {
+		let msg = SocketMessage::<()>::PlayerID(1, 0, 4, String::from("s3cr3t"));
+		let event = record(serde_json::to_string(&msg).unwrap());
+
+		assert!(!event.message.contains("s3cr3t"));
+		assert_eq!(event.message, r#"{"PlayerID":[1,0,4,"<redacted>"]}"#);
+	}
+
+	#[test]
+	fn other_messages_stay_untouched() {
+		let msg = SocketMessage::<()>::ChatMessage(String::from("{\"PlayerID\""), 1);
+		let json = serde_json::to_string(&msg).unwrap();
+		assert_eq!(record(json.clone()).message, json);
+	}
+
+	#[test]
+	fn nothing_is_recorded_without_watchers() {
+		let watch = Watch::default();
+		watch.record(Direction::Incoming, Peer::Everyone, || {
+			panic!("Serialized without watchers")
+		});
+	}
+}
@@ -1,9 +1,97 @@
 use serde::{Deserialize, Serialize};
 
+/// Why the server closes a connection.
+/// Sent as code and reason of the WebSocket close frame.
+#[derive(Clone, Copy, PartialEq, Eq, std::fmt::Debug)]
+pub enum CloseReason {
+	/// The client didn't introduce itself in time
+	Timeout,
+	/// The client sent something else than the expected messages
+	ProtocolMismatch,
+	RoomNotFound,
+	/// No free seat and no room for another spectator
+	RoomFull,
+	EmptyName,
+	/// The client was kicked from the room recently
+	Banned,
+	WrongPassword,
+	/// The client broke the rate or size limits too often
+	LimitExceeded,
+	/// The client was built for another version
+	UpgradeRequired,
+}
+
+impl CloseReason {
+	pub fn code(&self) -> u16 {
+		match self {
+			CloseReason::Timeout => 4000,
+			CloseReason::ProtocolMismatch => 4001,
+			CloseReason::RoomNotFound => 4002,
+			CloseReason::RoomFull => 4003,
+			CloseReason::EmptyName => 4004,
+			CloseReason::Banned => 4005,
+			CloseReason::WrongPassword => 4006,
+			CloseReason::LimitExceeded => 4007,
+			CloseReason::UpgradeRequired => 4008,
+		}
+	}
+
+	pub fn reason(&self) -> &'static str {
+		match self {
+			CloseReason::Timeout => "Handshake timed out",
+			CloseReason::ProtocolMismatch => "Protocol mismatch",
+			CloseReason::RoomNotFound => "Room not found",
+			CloseReason::RoomFull => "Room is full",
+			CloseReason::EmptyName => "Name is empty",
+			CloseReason::Banned => "Banned from the room",
+			CloseReason::WrongPassword => "Wrong password",
+			CloseReason::LimitExceeded => "Too many or too large messages",
+			CloseReason::UpgradeRequired => "Upgrade required",
+		}
+	}
+}
+
+/// Version of the messages defined here, increase it on every incompatible change
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Versions a client was built with, they have to match the ones of the server
+#[derive(Clone, Copy, PartialEq, Eq, std::fmt::Debug, Serialize, Deserialize)]
+pub struct Version {
+	pub protocol: u32,
+	pub game: u32,
+}
+
+/// Secret handed to a client, with which it can reclaim its seat after a disconnect
+pub type SessionToken = String;
+
 #[derive(Clone, PartialEq, std::fmt::Debug, Serialize, Deserialize)]
 #[non_exhaustive]
 pub struct ClientData {
 	pub name: String,
+
+	/// Token of a previous session, only ever sent by the client itself
+	#[serde(default, skip_serializing)]
+	pub session: Option<SessionToken>,
+
+	/// Password of the room, only ever sent by the client itself
+	#[serde(default, skip_serializing)]
+	pub secret: Option<String>,
+
+	/// Clients without a version are outdated
+	#[serde(default, skip_serializing)]
+	pub version: Option<Version>,
+
+	/// Whether the client only wants to watch the game
+	#[serde(default, skip_serializing)]
+	pub spectate: bool,
+
+	/// Whether the client is a bot player, only the server seats bots
+	#[serde(default, skip_deserializing)]
+	pub bot: bool,
+
+	/// Where the client connected from, set by the server
+	#[serde(skip)]
+	pub address: Option<std::net::IpAddr>,
 }
 
 #[derive(Clone, PartialEq, std::fmt::Debug, Serialize, Deserialize)]
@@ -41,7 +129,11 @@ pub enum SocketMessage<T> {
 	ClientDisconnected(usize),
 	JoinedClients(Vec<(ClientData, usize, usize)>),
 
-	PlayerID(usize, usize, usize),
+	SpectatorJoined(ClientData, usize),
+	JoinedSpectators(Vec<(ClientData, usize)>),
+
+	PlayerID(usize, usize, usize, SessionToken),
+	SpectatorID(usize, usize),
 	ChatMessage(String, usize),
 
 	PlayerOrder(Vec<(usize, usize)>),
@@ -49,6 +141,29 @@ pub enum SocketMessage<T> {
 	StartMating,
 	Mate(usize),
 
+	/// Seat a bot on a free seat, only the host may do so before the game starts
+	AddBot,
+
+	/// The player, who has to act, and the remaining seconds. None if there is no deadline
+	TurnDeadline(Option<(usize, u64)>),
+
+	/// Round-trip time of the players in milliseconds, by client id
+	Latency(Vec<(usize, u64)>),
+
+	/// The server is going down for maintenance, the message explains why
+	Maintenance(String),
+	/// A message of the admins to every client
+	Notice(String),
+	/// The versions of the client don't match, contains the versions of the server
+	UpgradeRequired(Version),
 	Ping,
 	Pong,
 }
+
+/// Sent to a client, whose last event was rejected.
+/// Apart from SocketMessage, since the errors of a game aren't its events
+#[derive(Clone, PartialEq, std::fmt::Debug, Serialize, Deserialize)]
+pub enum ErrorMessage<E> {
+	/// Contains the error of the game
+	Error(E),
+}
@@ -0,0 +1,156 @@
+//! The ways messages get to a client.
+//!
+//! Rooms only ever talk to a `Transport`, so clients don't need to be connected
+//! with a WebSocket. Tests use an in-memory channel, bots don't read their messages,
+//! other programs can talk newline-delimited JSON over TCP.
+
+use async_trait::async_trait;
+use axum::extract::ws::WebSocket;
+use futures::stream::SplitSink;
+use futures::SinkExt;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+
+/// A frame sent to a client, the same for every transport
+pub use axum::extract::ws::Message;
+
+#[async_trait]
+pub trait Transport: Send {
+	async fn send(&mut self, msg: Message) -> Result<(), axum::Error>;
+
+	/// Close the connection, the client can't receive anything afterwards
+	async fn close(&mut self) -> Result<(), axum::Error>;
+}
+
+pub type WsWriter = SplitSink<WebSocket, Message>;
+
+#[async_trait]
+impl Transport for WsWriter {
+	async fn send(&mut self, msg: Message) -> Result<(), axum::Error> {
+		SinkExt::send(self, msg).await
+	}
+
+	async fn close(&mut self) -> Result<(), axum::Error> {
+		SinkExt::close(self).await
+	}
+}
+
+/// In-memory transport, the messages arrive at the receiver returned by `new`
+pub struct ChannelTransport {
+	tx: Option<mpsc::UnboundedSender<Message>>,
+}
+
+impl ChannelTransport {
+	pub fn new() -> (Self, mpsc::UnboundedReceiver<Message>) {
+		let (tx, rx) = mpsc::unbounded_channel();
+		(Self { tx: Some(tx) }, rx)
+	}
+}
+
+#[async_trait]
+impl Transport for ChannelTransport {
+	async fn send(&mut self, msg: Message) -> Result<(), axum::Error> {
+		match &self.tx {
+			Some(tx) => tx.send(msg).map_err(axum::Error::new),
+			None => Err(axum::Error::new("Channel is closed")),
+		}
+	}
+
+	async fn close(&mut self) -> Result<(), axum::Error> {
+		// The receiver sees the end of the channel, once the sender is dropped
+		self.tx = None;
+		Ok(())
+	}
+}
+
+/// Transport of clients, which don't read their messages, like bots
+pub struct NullTransport;
+
+#[async_trait]
+impl Transport for NullTransport {
+	async fn send(&mut self, _msg: Message) -> Result<(), axum::Error> {
+		Ok(())
+	}
+
+	async fn close(&mut self) -> Result<(), axum::Error> {
+		Ok(())
+	}
+}
+
+/// Newline-delimited JSON over a byte stream.
+/// Every text message is written as one line, a close frame shuts the stream down.
+pub struct NdjsonTransport<W> {
+	writer: W,
+}
+
+/// Newline-delimited JSON over a TCP connection
+pub type TcpTransport = NdjsonTransport<tokio::net::tcp::OwnedWriteHalf>;
+
+impl<W> NdjsonTransport<W> {
+	pub fn new(writer: W) -> Self {
+		Self { writer }
+	}
+}
+
+#[async_trait]
+impl<W> Transport for NdjsonTransport<W>
+where
+	W: AsyncWrite + Unpin + Send,
+{
+	async fn send(&mut self, msg: Message) -> Result<(), axum::Error> {
+		match msg {
+			Message::Text(text) => {
+				// Serialized JSON never contains a raw newline
+				let line = format!("{}\n", text.as_str());
+
+				self.writer
+					.write_all(line.as_bytes())
+					.await
+					.map_err(axum::Error::new)?;
+				self.writer.flush().await.map_err(axum::Error::new)
+			}
+			Message::Close(_) => self.close().await,
+			// WebSocket pings and binary frames mean nothing here
+			_ => Ok(()),
+		}
+	}
+
+	async fn close(&mut self) -> Result<(), axum::Error> {
+		self.writer.shutdown().await.map_err(axum::Error::new)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use tokio::io::{AsyncBufReadExt, BufReader};
+
+	#[tokio::test]
+	async fn every_message_is_a_line() {
+		let (writer, reader) = tokio::io::duplex(1024);
+		let mut transport = NdjsonTransport::new(writer);
+		let mut lines = BufReader::new(reader).lines();
+
+		transport
+			.send(Message::Text("\"Ping\"".into()))
+			.await
+			.unwrap();
+		transport.send(Message::Ping(vec![1].into())).await.unwrap();
+		transport
+			.send(Message::Text("{\"Latency\":[]}".into()))
+			.await
+			.unwrap();
+		transport.send(Message::Close(None)).await.unwrap();
+
+		assert_eq!(
+			lines.next_line().await.unwrap().as_deref(),
+			Some("\"Ping\"")
+		);
+		assert_eq!(
+			lines.next_line().await.unwrap().as_deref(),
+			Some("{\"Latency\":[]}")
+		);
+		// The stream ends with the close frame
+		assert_eq!(lines.next_line().await.unwrap(), None);
+	}
+}
@@ -1,4 +1,12 @@
-import { Cardset, Event, Game, setting_classic, Trick } from "../pkg/tichu_game.js";
+import {
+	Cardset,
+	Event,
+	Game,
+	game_version,
+	LegalityError,
+	setting_classic,
+	Trick,
+} from "../pkg/tichu_game.js";
 import { CommHandler, MessageType } from "./chat.js";
 import { ClientSetting } from "./clientsetting.js";
 import { UI } from "./interface.js";
@@ -19,7 +27,7 @@ export class Main {
 	vote: VoteHandler;
 
 	constructor(addr: string, setting: ClientSetting) {
-		this.wshandler = new Wshandler(addr, setting);
+		this.wshandler = new Wshandler(addr, setting, game_version());
 
 		this.setting = setting;
 		this.ui = new UI(this.game);
@@ -27,6 +35,7 @@ export class Main {
 		this.vote = new VoteHandler($("body"), (idx) => this.wshandler.vote(idx));
 
 		this.wshandler.oninit = (c, p, n) => this.oninit(c, p, n);
+		this.wshandler.onspectate = (c, n) => this.onspectate(c, n);
 
 		this.wshandler.onclient = (d, c, p) => this.onclient(d, c, p);
 		this.wshandler.onclientleave = (c) => this.onclientleave(c);
@@ -34,6 +43,7 @@ export class Main {
 		this.wshandler.onchatmessage = (msg, c) => this.onchatmessage(msg, c);
 
 		this.wshandler.onevent = (ev) => this.onevent(ev);
+		this.wshandler.ongameerror = (e) => this.ongameerror(e);
 
 		this.wshandler.rtc_onstart = (cid) => this.comm.rtc_onstart(cid);
 		this.wshandler.rtc_onoffer = (cid, offer) => this.comm.rtc_onoffer(cid, offer);
@@ -44,6 +54,17 @@ export class Main {
 		this.wshandler.onvote = (v, cid) => this.vote.onvote(v, cid);
 		this.wshandler.onnewvote = (ty) => this.vote.onnewvote(ty);
 		this.wshandler.onvotequit = () => this.vote.onvotequit();
+		this.comm.kick_callback = (cid) => this.wshandler.proposeKick(cid);
+		this.comm.bot_callback = () => this.wshandler.addBot();
+
+		// Team choosing
+		this.comm.mate_callback = (cid) => this.wshandler.mate(cid);
+		this.wshandler.onstartmating = () => this.comm.onstartmating();
+		this.wshandler.onmaintenance = (notice: string) => this.comm.onmaintenance(notice);
+		this.wshandler.onnotice = (notice: string) => this.comm.onnotice(notice);
+		this.wshandler.ondeadline = (d) => this.ui.players.ondeadline(d);
+		this.wshandler.onlatency = (d) => this.ui.players.onlatency(d);
+		this.wshandler.onmate = (cid) => this.comm.onmate(cid);
 	}
 
 	// ---
@@ -58,8 +79,16 @@ export class Main {
 		this.ui.player_names.set(player_id, this.setting.name);
 	}
 
+	// Spectators watch the table from the first player's seat
+	onspectate(client_id: ClientID, num_players: number) {
+		this.client_id = client_id;
+
+		this.comm.oninit(client_id, this.player_id);
+		this.ui.oninit(client_id, this.player_id, num_players);
+	}
+
 	onclient(data: ClientData, client_id: ClientID, player_id: PlayerID) {
-		this.vote.onclient();
+		this.vote.onclient(data, client_id);
 		this.ui.onclient(data, client_id, player_id);
 		this.comm.onclient(data, client_id, player_id);
 	}
@@ -180,6 +209,17 @@ export class Main {
 		this.ui.players.setMessage(msg, player_id);
 	}
 
+	ongameerror(error: LegalityError) {
+		const messages: { [key in LegalityError]: string } = {
+			WrongTime: "You can't do that right now.",
+			IllegalWish: "This wish is not allowed.",
+			UnfulfilledWish: "You have to fulfill the current wish.",
+			TrickTooWeak: "Your trick can't beat the current one.",
+			MissingCards: "You don't own these cards.",
+		};
+		this.comm.chatMessage(MessageType.Info, messages[error]);
+	}
+
 	// ---
 
 	onevent(data: Event) {
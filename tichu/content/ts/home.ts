@@ -1,35 +1,45 @@
 import { createForm } from "./formcreator.js";
 import { get_client_settings, getClientSettingForm, save_client_setting } from "./clientsetting.js";
-import { construct_fetch_url, enter_room, get_rooms, request_room } from "./roomfinder.js";
+import {
+	construct_fetch_url,
+	construct_lobby_url,
+	enter_room,
+	request_room,
+	watch_lobby,
+} from "./roomfinder.js";
 
 import init, { get_gamesettingform, setting_classic } from "../pkg/tichu_game.js";
 import { get_setting_form, save_tichu_setting } from "./tichusettings.js";
 
 const URL = construct_fetch_url(7998);
+const LOBBY_URL = construct_lobby_url(7998);
 
-async function update_rooms() {
-	let rooms = undefined;
-
-	try {
-		rooms = await get_rooms(URL);
-	} catch (e) {
+function update_rooms() {
+	watch_lobby(LOBBY_URL, show_rooms, () => {
 		$("#rooms").text("Could not connect to server!").addClass("Error");
-		return;
-	}
+	});
+}
 
+function show_rooms(rooms: any[]) {
 	let eles = rooms.map((room: any) => {
 		let ele = $("<div>").addClass("Room");
 		let join = $("<button>")
 			.text("Enter")
-			.click(() => enter_room(room.id));
+			.click(() => {
+				if (!room.password) return enter_room(room.id);
+
+				let secret = prompt("Password of the room");
+				if (secret !== null) enter_room(room.id, secret);
+			});
 
 		let ratio = `${room.players.length}/${room.max_players}`;
 		let text = `Room ${room.id} (${ratio})`;
 
 		let title = $("<h2>").text(text);
+		let summary = $("<p>").text(room.summary);
 		let names = $("<p>").text(room.players.join(", "));
 
-		ele.append(title).append(names).append($("<p>").append(join));
+		ele.append(title).append(summary).append(names).append($("<p>").append(join));
 
 		return ele;
 	});
@@ -91,11 +101,13 @@ window.onload = async () => {
 		if (result === "classic") data = setting_classic();
 		if (result.hasOwnProperty("custom")) data = result["custom"];
 
-		let response = await request_room(URL, data);
+		let is_private = $("#roomPrivate").is(":checked");
+		let password = String($("#roomPassword").val() ?? "");
+		let response = await request_room(URL, data, is_private, password);
 
 		if (response.status === 200) {
 			let room_id = await response.text();
-			enter_room(room_id);
+			enter_room(room_id, password);
 		} else {
 			$("#settingInfo").text(
 				"An error occured while creating a room. Have you entered valid data?",
@@ -104,8 +116,9 @@ window.onload = async () => {
 	});
 	$("#manualRoomEnter").click(() => {
 		let id = $("#roomInput").val();
+		let secret = String($("#roomSecret").val() ?? "");
 		if (typeof id === "string" || typeof id === "number") {
-			enter_room(id);
+			enter_room(id, secret);
 		}
 	});
 
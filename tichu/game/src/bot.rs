@@ -0,0 +1,59 @@
+use game_server::room::bot::Bot;
+
+use crate::server::TichuRoom;
+use crate::*;
+use Event::*;
+
+/// The lowest single card the player may play, if there is one
+pub fn lowest_single(game: &Game, plr_id: usize) -> Option<Trick> {
+	game.players[plr_id]
+		.cards
+		.as_vec()
+		.into_iter()
+		.filter_map(|card| Trick::try_from(card).ok())
+		.find(|trick| game.legal_to_play(trick, None, plr_id).is_ok())
+}
+
+/// The lowest cards of the player, one for each other player
+pub fn cards_to_exchange(game: &Game, plr_id: usize) -> Vec<Card> {
+	let num = game.players.len() - 1;
+	game.players[plr_id]
+		.cards
+		.as_vec()
+		.into_iter()
+		.take(num)
+		.collect()
+}
+
+/// Never announces, gives away its lowest cards and plays single cards only
+#[derive(Default)]
+pub struct TichuBot;
+
+impl Bot<TichuRoom, Event> for TichuBot {
+	fn act(&mut self, room: &TichuRoom, plr_id: usize) -> Option<Event> {
+		let game = &room.game;
+		if game.should_game_end() {
+			return None;
+		}
+
+		match game.phase {
+			Phase::Distributing if game.can_announce_gt(plr_id) => {
+				Some(DecideGrandTichu(false, plr_id))
+			}
+			Phase::Exchange if game.players[plr_id].exchange.is_empty() => {
+				Some(ExchangeCards(cards_to_exchange(game, plr_id)))
+			}
+			Phase::GiveAway if game.current_player == plr_id => (0..game.players.len())
+				.find(|&target| game.can_give_away(target, plr_id))
+				.map(GiveAway),
+			Phase::Playing if game.current_player == plr_id => {
+				match lowest_single(game, plr_id) {
+					Some(trick) => Some(Play(trick, plr_id)),
+					None if game.can_pass(plr_id) => Some(Pass(plr_id)),
+					None => None,
+				}
+			}
+			_ => None,
+		}
+	}
+}
@@ -3,6 +3,8 @@ pub mod setting;
 
 pub mod trick;
 
+#[cfg(feature = "server")]
+pub mod bot;
 #[cfg(feature = "server")]
 pub mod server;
 
@@ -15,6 +17,14 @@ use thiserror::Error;
 use tsify_next::Tsify;
 use wasm_bindgen::prelude::*;
 
+/// Version of the game protocol, increase it on every incompatible change of `Event`
+pub const GAME_VERSION: u32 = 1;
+
+#[wasm_bindgen]
+pub fn game_version() -> u32 {
+	GAME_VERSION
+}
+
 #[derive(Tsify)]
 #[tsify(into_wasm_abi, from_wasm_abi)]
 #[derive(Clone, Eq, PartialEq, Serialize, Deserialize)]
@@ -357,6 +367,11 @@ impl Game {
 
 		match &trick {
 			Trick::Dog => {
+				// The dog can be the last card too
+				if self.players[plr_id].num_cards == 0 {
+					self.handle_finish(plr_id);
+				}
+
 				let next = self.next_player_of_team(self.current_player);
 				self.current_player = next;
 				self.last_player = next;
@@ -1,5 +1,6 @@
+use crate::bot::*;
 use crate::setting::Setting;
-use game_server::room::{client::*, *};
+use game_server::room::{bot::Bot, client::*, *};
 
 use async_trait::*;
 
@@ -7,14 +8,47 @@ use crate::*;
 
 use Event::*;
 
-#[derive(Clone, Eq, PartialEq)]
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct TichuRoom {
 	starts: u32,
 	pile: Cardset,
-	game: Game,
+	pub(crate) game: Game,
 }
 
 impl TichuRoom {
+	/// Decide against grand tichu and exchange the lowest cards for the player.
+	/// When playing, pass or, if the player has to lead, play a single card
+	async fn act_for(&mut self, clients: &mut ClientHandler, plr: usize) {
+		match self.game.phase {
+			Phase::Distributing if self.game.can_announce_gt(plr) => {
+				self.handle_gt(clients, false, plr).await;
+				return;
+			}
+			Phase::Exchange => {
+				let cards = cards_to_exchange(&self.game, plr);
+				self.handle_exchange(clients, cards, plr).await;
+				return;
+			}
+			_ => {}
+		}
+
+		if self.game.can_pass(plr) {
+			let _ = self.handle_pass(clients, plr).await;
+			return;
+		}
+
+		if let Some(trick) = lowest_single(&self.game, plr) {
+			let _ = self.handle_trick(clients, trick, None, plr).await;
+			return;
+		}
+
+		// The dragon won the trick and has to be given away
+		let num_players = self.game.players.len();
+		if let Some(target) = (0..num_players).find(|&t| self.game.can_give_away(t, plr)) {
+			let _ = self.handle_give_away(clients, target, plr).await;
+		}
+	}
+
 	pub async fn start_round(&mut self, clients: &mut ClientHandler) {
 		self.game.start_new_round();
 		self.pile = Cardset::full();
@@ -45,11 +79,8 @@ impl TichuRoom {
 		trick: Trick,
 		wish: Option<u8>,
 		plr: usize,
-	) {
-		if let Err(e) = self.game.legal_to_play(&trick, wish, plr) {
-			eprintln!("Error on play: {}", e);
-			return;
-		}
+	) -> Result<(), LegalityError> {
+		self.game.legal_to_play(&trick, wish, plr)?;
 
 		self.game.play_trick(trick.clone(), plr);
 
@@ -66,6 +97,7 @@ impl TichuRoom {
 		if self.game.should_round_end() {
 			self.end_round(clients).await;
 		}
+		Ok(())
 	}
 
 	pub async fn handle_announce(
@@ -73,21 +105,26 @@ impl TichuRoom {
 		clients: &mut ClientHandler,
 		announce: TichuState,
 		plr: usize,
-	) {
+	) -> Result<(), LegalityError> {
 		if let TichuState::None = announce {
-			return;
+			return Ok(());
 		}
 		if !self.game.can_announce(plr) {
-			return;
+			return Err(LegalityError::WrongTime);
 		}
 
 		self.game.announce(announce, plr);
 		clients.ev_send_to_all(Announce(announce, plr)).await;
+		Ok(())
 	}
 
-	pub async fn handle_pass(&mut self, clients: &mut ClientHandler, plr: usize) {
+	pub async fn handle_pass(
+		&mut self,
+		clients: &mut ClientHandler,
+		plr: usize,
+	) -> Result<(), LegalityError> {
 		if !self.game.can_pass(plr) {
-			return;
+			return Err(LegalityError::WrongTime);
 		}
 
 		self.game.pass();
@@ -96,6 +133,7 @@ impl TichuRoom {
 		if self.game.should_round_end() {
 			self.end_round(clients).await;
 		}
+		Ok(())
 	}
 
 	pub async fn handle_exchange(
@@ -134,9 +172,9 @@ impl TichuRoom {
 		clients: &mut ClientHandler,
 		target: usize,
 		plr: usize,
-	) {
+	) -> Result<(), LegalityError> {
 		if !self.game.can_give_away(target, plr) {
-			return;
+			return Err(LegalityError::WrongTime);
 		}
 
 		self.game.give_away(plr);
@@ -145,6 +183,7 @@ impl TichuRoom {
 		if self.game.should_round_end() {
 			self.end_round(clients).await;
 		}
+		Ok(())
 	}
 
 	pub async fn handle_gt(&mut self, clients: &mut ClientHandler, announce: bool, plr: usize) {
@@ -204,9 +243,14 @@ impl TryFrom<Setting> for TichuRoom {
 
 #[async_trait]
 impl ServerRoom<Event> for TichuRoom {
-	type Err = ();
+	type Err = LegalityError;
+	const GAME_VERSION: u32 = crate::GAME_VERSION;
 
-	async fn start(&mut self, clients: &mut ClientHandler) -> Result<(), Self::Err> {
+	async fn start(
+		&mut self,
+		clients: &mut ClientHandler,
+		_num_players: usize,
+	) -> Result<(), Self::Err> {
 		self.game = Game::new(self.game.setting.clone());
 		self.starts += 1;
 
@@ -231,6 +275,17 @@ impl ServerRoom<Event> for TichuRoom {
 
 	async fn on_leave(&mut self, _clients: &mut ClientHandler, _plr_id: usize) {}
 
+	async fn on_spectate(&mut self, clients: &mut ClientHandler, client_id: usize) {
+		if self.starts > 0 {
+			let ev = State(self.game.public_clone(), Cardset::new());
+			clients.ev_send_to_client(client_id, ev).await;
+		} else {
+			clients
+				.ev_send_to_client(client_id, Setting(self.game.setting.clone()))
+				.await;
+		}
+	}
+
 	async fn on_event(
 		&mut self,
 		clients: &mut ClientHandler,
@@ -238,25 +293,194 @@ impl ServerRoom<Event> for TichuRoom {
 		plr_id: usize,
 	) -> Result<(), Self::Err> {
 		match event {
-			Play(cards, _) => self.handle_trick(clients, cards, None, plr_id).await,
-			WishPlay(cards, wish, _) => self.handle_trick(clients, cards, Some(wish), plr_id).await,
-			Pass(_) => self.handle_pass(clients, plr_id).await,
-			Announce(tichu, _) => self.handle_announce(clients, tichu, plr_id).await,
+			Play(cards, _) => self.handle_trick(clients, cards, None, plr_id).await?,
+			WishPlay(cards, wish, _) => {
+				self.handle_trick(clients, cards, Some(wish), plr_id).await?
+			}
+			Pass(_) => self.handle_pass(clients, plr_id).await?,
+			Announce(tichu, _) => self.handle_announce(clients, tichu, plr_id).await?,
 
 			ExchangeCards(cards) => self.handle_exchange(clients, cards, plr_id).await,
 			DecideGrandTichu(announce, _) => self.handle_gt(clients, announce, plr_id).await,
-			GiveAway(target) => self.handle_give_away(clients, target, plr_id).await,
+			GiveAway(target) => self.handle_give_away(clients, target, plr_id).await?,
 			_ => {}
 		}
 
 		Ok(())
 	}
 
+	/// The player, who has to play or pass
+	/// While everyone decides at once, like on grand tichu or the exchange,
+	/// the undecided players get the deadline one after another
+	fn on_turn(&self) -> Option<usize> {
+		if self.starts == 0 || self.game.should_game_end() {
+			return None;
+		}
+
+		let num_players = self.game.players.len();
+		match self.game.phase {
+			Phase::Distributing => (0..num_players).find(|&plr| self.game.can_announce_gt(plr)),
+			Phase::Exchange => {
+				(0..num_players).find(|&plr| self.game.players[plr].exchange.is_empty())
+			}
+			Phase::GiveAway | Phase::Playing => Some(self.game.current_player),
+		}
+	}
+
+	fn ends_turn(&self, event: &Event) -> bool {
+		// Announcing can be done during the turn, the decisions of the others
+		// don't end the turn of the undecided player
+		!matches!(event, Announce(..) | DecideGrandTichu(..) | ExchangeCards(_))
+	}
+
+	async fn on_timeout(&mut self, clients: &mut ClientHandler, plr_id: usize) {
+		self.act_for(clients, plr_id).await;
+	}
+
+	fn new_bot(&self) -> Option<Box<dyn Bot<Self, Event>>> {
+		Some(Box::new(TichuBot))
+	}
+
+	fn summary(&self) -> String {
+		match self.game.setting.end_condition {
+			setting::EndCondition::Points(points) => format!("Up to {} points", points),
+		}
+	}
+
 	fn get_player_bound(&self) -> (usize, usize) {
+		// Every seat has to be taken, so the room never votes to start early
 		let n = self.game.setting.num_players;
 		(n, n)
 	}
+	fn get_teams(&self) -> Vec<Vec<usize>> {
+		let mut teams = vec![vec![]; self.game.teams.len()];
+		for (plr_id, plr) in self.game.players.iter().enumerate() {
+			teams[plr.team_id].push(plr_id);
+		}
+		teams
+	}
 	fn should_end(&self) -> bool {
 		self.game.should_game_end()
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use game_server::socket_message::{ClientData, SocketMessage};
+	use game_server::transport::{ChannelTransport, Message};
+	use std::time::Duration;
+	use tokio::sync::mpsc::UnboundedReceiver;
+
+	type TestRoom = Room<Setting, Event, TichuRoom>;
+	type Inbox = UnboundedReceiver<Message>;
+
+	/// A room, in which four clients connected over channels started a game
+	async fn started_room() -> (TestRoom, Vec<(usize, Inbox)>) {
+		let setting = RoomSetting {
+			public: false,
+			password: None,
+			game_setting: Setting::default(),
+		};
+		let mut room = TestRoom::try_new(RoomID::from("TEST"), setting).unwrap();
+
+		let mut clients = vec![];
+		for name in ["Alice", "Bob", "Carol", "Dave"] {
+			let data: ClientData = serde_json::from_str(&format!("{{\"name\": \"{}\"}}", name)).unwrap();
+			let (transport, inbox) = ChannelTransport::new();
+			let (_, id) = room.register(data, Box::new(transport)).await;
+			clients.push((id, inbox));
+		}
+
+		// Nobody chooses a partner
+		for &(id, _) in clients.iter() {
+			room.handle_input(SocketMessage::Vote(0, id), id).await;
+		}
+		assert_eq!(room.state, RoomState::Playing);
+
+		(room, clients)
+	}
+
+	/// The game events a client received since the last call, none of its events may have been rejected
+	async fn events(inbox: &mut Inbox) -> Vec<Event> {
+		// Every client has its own writer task, let them catch up
+		tokio::task::yield_now().await;
+
+		let mut events = vec![];
+		while let Ok(msg) = inbox.try_recv() {
+			let Message::Text(text) = msg else { continue };
+			assert!(!text.as_str().starts_with("{\"Error\""), "{}", text.as_str());
+
+			if let Ok(SocketMessage::Event(event)) = serde_json::from_str(text.as_str()) {
+				events.push(event);
+			}
+		}
+		events
+	}
+
+	#[tokio::test]
+	async fn a_round_is_played_through_the_room() {
+		let (mut room, mut clients) = started_room().await;
+
+		// Everyone only sees their own cards
+		for (id, inbox) in clients.iter_mut() {
+			let cards = room.game.game.players[room.clients[id].player_id].cards.clone();
+			let dealt: Vec<Cardset> = events(inbox)
+				.await
+				.into_iter()
+				.filter_map(|event| match event {
+					StartDistribution(cards) => Some(cards),
+					_ => None,
+				})
+				.collect();
+			assert_eq!(dealt, vec![cards]);
+		}
+
+		// The clients make the moves of a bot, until the next round is dealt
+		let mut moves = 0;
+		let mut played = false;
+		while !played || room.game.game.phase != Phase::Distributing {
+			let (id, event) = clients
+				.iter()
+				.find_map(|(id, _)| {
+					let plr_id = room.clients[id].player_id;
+					TichuBot.act(&room.game, plr_id).map(|event| (*id, event))
+				})
+				.expect("Nobody can act");
+
+			moves += matches!(event, Play(..) | Pass(_)) as usize;
+			room.handle_input(SocketMessage::Event(event), id).await;
+			played |= room.game.game.phase == Phase::Playing;
+		}
+
+		// Everyone saw every move
+		for (_, inbox) in clients.iter_mut() {
+			let seen = events(inbox)
+				.await
+				.into_iter()
+				.filter(|event| matches!(event, Play(..) | Pass(_)))
+				.count();
+			assert_eq!(seen, moves);
+		}
+	}
+	#[tokio::test]
+	async fn idle_players_do_not_stall_the_game() {
+		let (mut room, mut clients) = started_room().await;
+		let inbox = &mut clients[0].1;
+
+		// Grand tichu, the exchange and every turn run out of time
+		let mut exchanged = 0;
+		let mut played = false;
+		while !played || room.game.game.phase != Phase::Distributing {
+			let plr_id = room.deadline.expect("Nobody is on turn").plr_id;
+			room.deadline = Some(Deadline::new(plr_id, Duration::ZERO));
+			room.tick().await;
+
+			for event in events(inbox).await {
+				exchanged += matches!(event, DidExchange(_)) as usize;
+			}
+			played |= room.game.game.phase == Phase::Playing;
+		}
+		assert_eq!(exchanged, clients.len());
+	}
+}
@@ -8,6 +8,7 @@ use game_server::*;
 async fn main() {
 	Server::new("0.0.0.0:7998", "Tichu")
 		// .unix_socket("unix_sock")
+		.snapshot_dir("/var/lib/tichu")
 		.build::<Setting, Event, TichuRoom>()
 		.await;
 }